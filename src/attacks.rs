@@ -0,0 +1,279 @@
+use crate::bitboard::Bitboard;
+use crate::types::Color;
+
+// precomputed attack tables, indexed by square index (a1 = 0, h8 = 63)
+// sliders use fancy magic bitboards: the occupancy of the squares a piece could be blocked on is hashed
+// into a per square slice of one shared table, which holds the attacks for every possible set of blockers
+
+// the pext feature replaces the magic multiply with the bmi2 pext instruction, which packs the blocker bits
+// into a perfect index, but it's slow on amd cpus before zen 3, so it's off by default
+#[cfg(all(feature = "pext", not(all(target_arch = "x86_64", target_feature = "bmi2"))))]
+compile_error!("the pext feature needs bmi2, build with RUSTFLAGS=\"-C target-cpu=native\" on a cpu that supports it");
+
+const ROOK_DELTAS: [(i32, i32); 4] = [(1, 0), (-1, 0), (0, 1), (0, -1)];
+const BISHOP_DELTAS: [(i32, i32); 4] = [(1, 1), (1, -1), (-1, 1), (-1, -1)];
+const KNIGHT_DELTAS: [(i32, i32); 8] = [(2, -1), (2, 1), (1, -2), (1, 2), (-2, -1), (-2, 1), (-1, -2), (-1, 2)];
+const KING_DELTAS: [(i32, i32); 8] = [(1, 0), (-1, 0), (0, 1), (0, -1), (1, 1), (1, -1), (-1, 1), (-1, -1)];
+// pawns capture diagonally forward, which is up the board for white and down it for black
+const PAWN_DELTAS: [[(i32, i32); 2]; 2] = [[(1, -1), (1, 1)], [(-1, -1), (-1, 1)]];
+
+// one slot per blocker subset of each square's mask: 2^popcount(mask) summed over the board
+const ROOK_TABLE_SIZE: usize = 102_400;
+const BISHOP_TABLE_SIZE: usize = 5_248;
+
+// found offline with a sparse random search, each one maps the square's blocker subsets to its slice without collisions
+const ROOK_MAGICS: [u64; 64] = [
+    0x208000148821C000, 0x0040001000200048, 0x1880200080100008, 0x1C80100080080004,
+    0x2600102008220004, 0x5100020100080400, 0x44800C8006001100, 0x0080108004426900,
+    0x1502002082004100, 0x0074400050002000, 0x0008802004100084, 0x0008801002800802,
+    0x2000800800040080, 0x240A000430481600, 0x0008800200804100, 0x0006000100A20044,
+    0x4420808000204000, 0x4C20220041020084, 0x1081010020004010, 0x0048010100201000,
+    0x0801010008020410, 0x8064004002004100, 0x0140840010080201, 0x9000A2000A528104,
+    0x8918400180082080, 0x0A00400080802000, 0x0010410100200014, 0x2000090100100020,
+    0x2008008080040009, 0x0008040080800200, 0x404108A400020110, 0x0020024600010184,
+    0x0980002001400140, 0x5020004020401000, 0x2020200080801004, 0x0030420022001008,
+    0x0010080080800400, 0x0042040080800200, 0x8400800100800200, 0xA400005082000104,
+    0x0080002000504000, 0x0260004000888023, 0x0808102001010040, 0x0088020100101000,
+    0x008C050008010010, 0x0011000400090002, 0x4002000108020084, 0x002014A144020005,
+    0x0080084100308100, 0xC0B0004000200040, 0x0A40108040220200, 0x6000800800100080,
+    0x0428810801040080, 0x0006000204008080, 0x0408023028010400, 0x6041000082006100,
+    0x4100800100102041, 0x002081023020C00B, 0x010A00802040100A, 0x8000201000080501,
+    0x0872002004100802, 0x400A000844015002, 0x5284020090381104, 0x0008003044010082
+];
+
+const BISHOP_MAGICS: [u64; 64] = [
+    0x8002981001020121, 0x000A902105011000, 0x4011080208400014, 0x0004042080288040,
+    0x0602021022021200, 0xC0008260A0000000, 0x0004088804D10800, 0x0811002119084000,
+    0x4050041014282080, 0x9840080184208200, 0x0101080084248400, 0x000211040080C104,
+    0x0200140D20420001, 0x0010209010080010, 0x0820220101084100, 0x0000020844140492,
+    0x011840401214A401, 0x0602098408080120, 0x0010000100448104, 0x0804001802420800,
+    0x8042100401202680, 0x0000200410041008, 0x0011045088080200, 0x224220010C010401,
+    0x0410420004481240, 0x3030CC2002180200, 0x1008110148020220, 0x2082008198008002,
+    0x1D21001007004005, 0x0008184012004200, 0x2001090822080109, 0x0004045603004208,
+    0x0012201000043140, 0xA000888820849006, 0x0000203002280080, 0x0854020080C80080,
+    0x0210048200002200, 0x0050010040420049, 0x431112040C82A400, 0x0110822200094500,
+    0x008601301A804000, 0x4000840188246086, 0x010504908A001000, 0x0000C04010404200,
+    0x0102010202010420, 0x8243A008008084C0, 0x01202801010E3048, 0x108102208D029200,
+    0x040402199008A000, 0x0400920082200800, 0x4002084200901401, 0x0001208042088000,
+    0x0219214192820222, 0x0844420448008480, 0x0020040458004004, 0x0220060C61408010,
+    0x200204C10C901008, 0x0880102401088880, 0x0010080100809001, 0x1840814801084804,
+    0x0400001060843400, 0x0409031202900100, 0x0400059084090400, 0x1008203800802088
+];
+
+struct SliderTable<const N: usize> {
+    masks: [Bitboard; 64], // squares whose occupancy can block the slider, excluding the board edge
+    magics: [u64; 64],
+    shifts: [u32; 64],
+    offsets: [usize; 64], // where each square's slice starts in attacks
+    attacks: [Bitboard; N]
+}
+
+// statics rather than consts so the tables are only built once, at compile time, and never copied
+// building the rook table takes several seconds of const evaluation, which rustc would otherwise stop as a likely infinite loop
+#[allow(long_running_const_eval)]
+static ROOK_TABLE: SliderTable<ROOK_TABLE_SIZE> = gen_slider_table(&ROOK_DELTAS, &ROOK_MAGICS);
+static BISHOP_TABLE: SliderTable<BISHOP_TABLE_SIZE> = gen_slider_table(&BISHOP_DELTAS, &BISHOP_MAGICS);
+static KNIGHT_ATTACKS: [Bitboard; 64] = gen_step_attacks(&KNIGHT_DELTAS);
+static KING_ATTACKS: [Bitboard; 64] = gen_step_attacks(&KING_DELTAS);
+static PAWN_ATTACKS: [[Bitboard; 64]; 2] = [gen_step_attacks(&PAWN_DELTAS[0]), gen_step_attacks(&PAWN_DELTAS[1])];
+static BETWEEN: [[Bitboard; 64]; 64] = gen_line_table(false);
+static LINES: [[Bitboard; 64]; 64] = gen_line_table(true);
+
+pub fn rook_attacks(sq: usize, occupied: Bitboard) -> Bitboard {
+    return ROOK_TABLE.attacks[ROOK_TABLE.index(sq, occupied)];
+}
+
+pub fn bishop_attacks(sq: usize, occupied: Bitboard) -> Bitboard {
+    return BISHOP_TABLE.attacks[BISHOP_TABLE.index(sq, occupied)];
+}
+
+pub fn queen_attacks(sq: usize, occupied: Bitboard) -> Bitboard {
+    return rook_attacks(sq, occupied) | bishop_attacks(sq, occupied);
+}
+
+pub fn knight_attacks(sq: usize) -> Bitboard {
+    return KNIGHT_ATTACKS[sq];
+}
+
+pub fn king_attacks(sq: usize) -> Bitboard {
+    return KING_ATTACKS[sq];
+}
+
+// the squares a pawn of color c on sq attacks
+pub fn pawn_attacks(c: Color, sq: usize) -> Bitboard {
+    return PAWN_ATTACKS[c as usize][sq];
+}
+
+// the squares strictly between two squares on the same rank, file or diagonal, or nothing if they don't share one
+pub fn between(sq1: usize, sq2: usize) -> Bitboard {
+    return BETWEEN[sq1][sq2];
+}
+
+// the whole rank, file or diagonal through two squares, edge to edge, or nothing if they don't share one
+pub fn line(sq1: usize, sq2: usize) -> Bitboard {
+    return LINES[sq1][sq2];
+}
+
+impl<const N: usize> SliderTable<N> {
+    #[inline(always)]
+    fn index(&self, sq: usize, occupied: Bitboard) -> usize {
+        return self.offsets[sq] + slot_index(occupied & self.masks[sq], self.masks[sq], self.magics[sq], self.shifts[sq]);
+    }
+}
+
+// where a set of blockers goes within its square's slice
+#[cfg(not(feature = "pext"))]
+#[inline(always)]
+fn slot_index(blockers: Bitboard, _mask: Bitboard, magic: u64, shift: u32) -> usize {
+    return (blockers.wrapping_mul(magic) >> shift) as usize;
+}
+
+#[cfg(feature = "pext")]
+#[inline(always)]
+fn slot_index(blockers: Bitboard, mask: Bitboard, _magic: u64, _shift: u32) -> usize {
+    // the compile_error above makes sure bmi2 is available whenever this is compiled
+    return unsafe { core::arch::x86_64::_pext_u64(blockers, mask) } as usize;
+}
+
+// the same index, for building the tables at compile time where the intrinsic can't be called
+// the carry-rippler below walks through the subsets in the order of their packed bits, so pext just gives the subset's number
+#[cfg(not(feature = "pext"))]
+const fn gen_slot_index(blockers: Bitboard, _subset_number: usize, magic: u64, shift: u32) -> usize {
+    return (blockers.wrapping_mul(magic) >> shift) as usize;
+}
+
+#[cfg(feature = "pext")]
+const fn gen_slot_index(_blockers: Bitboard, subset_number: usize, _magic: u64, _shift: u32) -> usize {
+    return subset_number;
+}
+
+const fn on_board(rank: i32, file: i32) -> bool {
+    return rank >= 0 && rank < 8 && file >= 0 && file < 8;
+}
+
+// attacks of a piece that moves one step along each delta, like knights and kings
+const fn gen_step_attacks(deltas: &[(i32, i32)]) -> [Bitboard; 64] {
+    let mut table = [0u64; 64];
+    let mut sq = 0;
+    while sq < 64 {
+        let mut i = 0;
+        while i < deltas.len() {
+            let (rank, file) = ((sq / 8) as i32 + deltas[i].0, (sq % 8) as i32 + deltas[i].1);
+            if on_board(rank, file) {
+                table[sq] |= 1u64 << (rank * 8 + file);
+            }
+            i += 1;
+        }
+        sq += 1;
+    }
+    return table;
+}
+
+// attacks of a slider on sq, going along each delta until it leaves the board or hits a piece in occupied, one square at a time
+// with skip_edge set, the last square in each direction is left out, which gives the blocker mask
+const fn gen_slider_attacks(sq: usize, occupied: Bitboard, deltas: &[(i32, i32)], skip_edge: bool) -> Bitboard {
+    let mut attacks = 0u64;
+    let mut i = 0;
+    while i < deltas.len() {
+        let (dr, df) = deltas[i];
+        let (mut rank, mut file) = ((sq / 8) as i32 + dr, (sq % 8) as i32 + df);
+        while on_board(rank, file) && (!skip_edge || on_board(rank + dr, file + df)) {
+            let bb = 1u64 << (rank * 8 + file);
+            attacks |= bb;
+            if occupied & bb != 0 {
+                break;
+            }
+            rank += dr;
+            file += df;
+        }
+        i += 1;
+    }
+    return attacks;
+}
+
+// for every pair of squares on a common line, either the squares between them or the whole line through them
+const fn gen_line_table(whole_line: bool) -> [[Bitboard; 64]; 64] {
+    let mut table = [[0u64; 64]; 64];
+    let mut sq = 0;
+    while sq < 64 {
+        let mut i = 0;
+        while i < KING_DELTAS.len() {
+            let (dr, df) = KING_DELTAS[i];
+            let full_line = (1u64 << sq) | gen_slider_attacks(sq, 0, &[(dr, df), (-dr, -df)], false);
+            let mut passed = 0u64;
+            let (mut rank, mut file) = ((sq / 8) as i32 + dr, (sq % 8) as i32 + df);
+            while on_board(rank, file) {
+                let other = (rank * 8 + file) as usize;
+                table[sq][other] = if whole_line { full_line } else { passed };
+                passed |= 1u64 << other;
+                rank += dr;
+                file += df;
+            }
+            i += 1;
+        }
+        sq += 1;
+    }
+    return table;
+}
+
+// attacks of a slider on sq with the given blockers, from the empty board ray in each direction of the slider
+// a blocked ray loses everything past the nearest blocker, which is just the same direction's ray from the blocker
+const fn gen_blocked_attacks(sq: usize, blockers: Bitboard, deltas: &[(i32, i32)], rays: &[[Bitboard; 64]; 4]) -> Bitboard {
+    let mut attacks = 0u64;
+    let mut i = 0;
+    while i < deltas.len() {
+        let mut ray = rays[i][sq];
+        let hits = ray & blockers;
+        if hits != 0 {
+            // directions that go up the board increase the square index, so the nearest blocker is the lowest one
+            let nearest = if deltas[i].0 * 8 + deltas[i].1 > 0 { hits.trailing_zeros() } else { 63 - hits.leading_zeros() };
+            ray ^= rays[i][nearest as usize];
+        }
+        attacks |= ray;
+        i += 1;
+    }
+    return attacks;
+}
+
+const fn gen_slider_table<const N: usize>(deltas: &[(i32, i32); 4], magics: &[u64; 64]) -> SliderTable<N> {
+    let mut rays = [[0u64; 64]; 4];
+    let mut sq = 0;
+    while sq < 64 {
+        let mut i = 0;
+        while i < 4 {
+            rays[i][sq] = gen_slider_attacks(sq, 0, &[deltas[i]], false);
+            i += 1;
+        }
+        sq += 1;
+    }
+
+    let mut table = SliderTable { masks: [0; 64], magics: *magics, shifts: [0; 64], offsets: [0; 64], attacks: [0; N] };
+    let mut offset = 0;
+    let mut sq = 0;
+    while sq < 64 {
+        let mask = gen_slider_attacks(sq, 0, deltas, true);
+        table.masks[sq] = mask;
+        table.shifts[sq] = 64 - mask.count_ones();
+        table.offsets[sq] = offset;
+
+        // walk through every subset of the mask (the carry-rippler trick) and store its attacks
+        let mut blockers = 0u64;
+        let mut subset_number = 0;
+        loop {
+            let idx = offset + gen_slot_index(blockers, subset_number, magics[sq], table.shifts[sq]);
+            let attacks = gen_blocked_attacks(sq, blockers, deltas, &rays);
+            // blocker sets may share a slot, but only if they leave the slider the same attacks
+            assert!(table.attacks[idx] == 0 || table.attacks[idx] == attacks, "bad magic number");
+            table.attacks[idx] = attacks;
+            blockers = blockers.wrapping_sub(mask) & mask;
+            subset_number += 1;
+            if blockers == 0 {
+                break;
+            }
+        }
+        offset += 1 << mask.count_ones();
+        sq += 1;
+    }
+    assert!(offset == N, "slider table size doesn't match its masks");
+    return table;
+}
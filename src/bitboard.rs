@@ -226,7 +226,7 @@ pub fn square_to_bitboard(square: types::Square) -> Bitboard {
 }
 
 pub fn bitboard_from_index(i: u32) -> Bitboard {
-    return (0x1 << i);
+    return 0x1 << i;
 }
 
 pub fn flip_bitboard(b: Bitboard) -> Bitboard {
@@ -235,7 +235,7 @@ pub fn flip_bitboard(b: Bitboard) -> Bitboard {
         let shift1 = i*8;
         let shift2 = 56 - shift1;
         let mask = 0xFFu64 << shift1;
-        res |= (((b & mask) >> shift1) << shift2);
+        res |= ((b & mask) >> shift1) << shift2;
     }
     return res;
 }
@@ -268,11 +268,11 @@ pub fn slide(b: Bitboard, dir: Direction, dist: i32) -> Bitboard {
     match dir {
         Direction::N => {
             let mask = ALL_SQUARES >> (8*dist);
-            return (b & mask) << 8*dist;
+            return (b & mask) << (8*dist);
         },
         Direction::S => {
             let mask = ALL_SQUARES << (8*dist);
-            return (b & mask) >> 8*dist;
+            return (b & mask) >> (8*dist);
         },
         Direction::E => {
             let row_mask = 0xFFu8 >> dist;
@@ -287,22 +287,22 @@ pub fn slide(b: Bitboard, dir: Direction, dist: i32) -> Bitboard {
         Direction::NW => {
             let row_mask = 0xFFu8 << dist;
             let mask = (ALL_SQUARES >> (8*dist)) & u64::from_ne_bytes([row_mask, row_mask, row_mask, row_mask, row_mask, row_mask, row_mask, row_mask]);
-            return (b & mask) << 7*dist;
+            return (b & mask) << (7*dist);
         },
         Direction::NE => {
             let row_mask = 0xFFu8 >> dist;
             let mask = (ALL_SQUARES >> (8*dist)) & u64::from_ne_bytes([row_mask, row_mask, row_mask, row_mask, row_mask, row_mask, row_mask, row_mask]);
-            return (b & mask) << 9*dist;
+            return (b & mask) << (9*dist);
         },
         Direction::SW => {
             let row_mask = 0xFFu8 << dist;
             let mask = (ALL_SQUARES << (8*dist)) & u64::from_ne_bytes([row_mask, row_mask, row_mask, row_mask, row_mask, row_mask, row_mask, row_mask]);
-            return (b & mask) >> 9*dist;
+            return (b & mask) >> (9*dist);
         },
         Direction::SE => {
             let row_mask = 0xFFu8 >> dist;
             let mask = (ALL_SQUARES << (8*dist)) & u64::from_ne_bytes([row_mask, row_mask, row_mask, row_mask, row_mask, row_mask, row_mask, row_mask]);
-            return (b & mask) >> 7*dist;
+            return (b & mask) >> (7*dist);
         }
     }
 }
@@ -328,9 +328,9 @@ pub fn get_pieces_material_value(p: Pieces, g: GamePhase) -> i32 {
     let idx = bb.trailing_zeros();
     if bb != 0 {
         if g == GamePhase::Endgame {
-            eval += eval::KING_ENDGAME_EVAL[(idx as usize)];
+            eval += eval::KING_ENDGAME_EVAL[idx as usize];
         } else {
-            eval += eval::KING_EVAL[(idx as usize)];
+            eval += eval::KING_EVAL[idx as usize];
         }
     }
 
@@ -339,7 +339,7 @@ pub fn get_pieces_material_value(p: Pieces, g: GamePhase) -> i32 {
     while bb != 0 {
         let idx = bb.trailing_zeros();
         let idx_bb = bitboard_from_index(idx);
-        eval += eval::QUEEN_EVAL[(idx as usize)];
+        eval += eval::QUEEN_EVAL[idx as usize];
         bb &= !idx_bb;
     }
 
@@ -348,7 +348,7 @@ pub fn get_pieces_material_value(p: Pieces, g: GamePhase) -> i32 {
     while bb != 0 {
         let idx = bb.trailing_zeros();
         let idx_bb = bitboard_from_index(idx);
-        eval += eval::ROOK_EVAL[(idx as usize)];
+        eval += eval::ROOK_EVAL[idx as usize];
         bb &= !idx_bb;
     }
 
@@ -357,7 +357,7 @@ pub fn get_pieces_material_value(p: Pieces, g: GamePhase) -> i32 {
     while bb != 0 {
         let idx = bb.trailing_zeros();
         let idx_bb = bitboard_from_index(idx);
-        eval += eval::BISHOP_EVAL[(idx as usize)];
+        eval += eval::BISHOP_EVAL[idx as usize];
         bb &= !idx_bb;
     }
 
@@ -366,7 +366,7 @@ pub fn get_pieces_material_value(p: Pieces, g: GamePhase) -> i32 {
     while bb != 0 {
         let idx = bb.trailing_zeros();
         let idx_bb = bitboard_from_index(idx);
-        eval += eval::KNIGHT_EVAL[(idx as usize)];
+        eval += eval::KNIGHT_EVAL[idx as usize];
         bb &= !idx_bb;
     }
 
@@ -375,7 +375,7 @@ pub fn get_pieces_material_value(p: Pieces, g: GamePhase) -> i32 {
     while bb != 0 {
         let idx = bb.trailing_zeros();
         let idx_bb = bitboard_from_index(idx);
-        eval += eval::PAWN_EVAL[(idx as usize)];
+        eval += eval::PAWN_EVAL[idx as usize];
         bb &= !idx_bb;
     }
 
@@ -384,49 +384,41 @@ pub fn get_pieces_material_value(p: Pieces, g: GamePhase) -> i32 {
 
 pub fn bitboard_pretty_print(b: Bitboard) {
     let mut chars: [char; 64] = ['.'; 64];
-    for i in 0..64 {
+    for (i, c) in chars.iter_mut().enumerate() {
         if (b & (0x1 << i)) != 0 {
-            chars[i] = '*';
+            *c = '*';
         }
     }
     print!("8   ");
-    for i in 56..64 {
-        let c = chars[i];
+    for &c in chars[56..64].iter() {
         print!("{} ", c);
     }
     print!("\n7   ");
-    for i in 48..56 {
-        let c = chars[i];
+    for &c in chars[48..56].iter() {
         print!("{} ", c);
     }
     print!("\n6   ");
-    for i in 40..48 {
-        let c = chars[i];
+    for &c in chars[40..48].iter() {
         print!("{} ", c);
     }
     print!("\n5   ");
-    for i in 32..40 {
-        let c = chars[i];
+    for &c in chars[32..40].iter() {
         print!("{} ", c);
     }
     print!("\n4   ");
-    for i in 24..32 {
-        let c = chars[i];
+    for &c in chars[24..32].iter() {
         print!("{} ", c);
     }
     print!("\n3   ");
-    for i in 16..24 {
-        let c = chars[i];
+    for &c in chars[16..24].iter() {
         print!("{} ", c);
     }
     print!("\n2   ");
-    for i in 8..16 {
-        let c = chars[i];
+    for &c in chars[8..16].iter() {
         print!("{} ", c);
     }
     print!("\n1   ");
-    for i in 0..8 {
-        let c = chars[i];
+    for &c in chars[0..8].iter() {
         print!("{} ", c);
     }
     println!("\n\n    A B C D E F G H");
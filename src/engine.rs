@@ -0,0 +1,195 @@
+use std::fs::{File, OpenOptions};
+use std::io::Write;
+use std::sync::Arc;
+use std::sync::atomic::Ordering;
+use std::thread;
+use std::time::Duration;
+
+use crate::types::{Board, Move, History};
+use crate::utils;
+use crate::move_search;
+use crate::search;
+use crate::search::{SearchLimits, SearchOptions, SearchSignals, SearchResult};
+use crate::tt::{self, TranspositionTable};
+use crate::options::{self, UciOption, OptionValue};
+
+// everything the uci loop keeps between commands
+pub struct Engine {
+    pub pos: Board,
+    pub history: History, // the game so far, for repetition detection
+    pub tt: Arc<TranspositionTable>, // shared with the search threads
+    pub options: SearchOptions,
+    signals: SearchSignals,
+    search_thread: Option<thread::JoinHandle<()>>,
+    log_file: Option<File> // every command we receive gets appended to this
+}
+
+impl Engine {
+    pub fn new() -> Engine {
+        return Engine {
+            pos: utils::START_POSITION,
+            history: History::new(&utils::START_POSITION),
+            tt: Arc::new(TranspositionTable::new(tt::DEFAULT_HASH_MB)),
+            options: SearchOptions::default(),
+            signals: SearchSignals::default(),
+            search_thread: None,
+            log_file: None
+        };
+    }
+
+    // forgets everything we learned about the previous game
+    // anything that carries over from one search to the next must be reset here
+    pub fn new_game(&mut self) {
+        self.stop_search();
+        self.tt.clear();
+        self.pos = utils::START_POSITION;
+        self.history = History::new(&self.pos);
+    }
+
+    // sets up a position from a start position and the uci moves played from it, recording each position in the history
+    // apply_move trusts the flags of the moves it's given, so we take each move from our legal moves instead of
+    // working its flags out from the board, and stop at the first one that isn't legal
+    pub fn set_position<'a>(&mut self, b: Board, moves: impl Iterator<Item = &'a str>) {
+        self.stop_search();
+        self.pos = b;
+        self.history = History::new(&self.pos);
+        for m_str in moves {
+            let legal_move = Move::from_uci(m_str).and_then(|m| move_search::calc_moves(&self.pos).iter().find(|lm| lm.to_move() == m));
+            match legal_move {
+                Some(m) => {
+                    let next_pos = utils::apply_move(&self.pos, m);
+                    self.history.push(&next_pos, utils::is_irreversible(&self.pos, &next_pos));
+                    self.pos = next_pos;
+                },
+                _ => {
+                    println!("ERROR: illegal or unparseable move, ignoring it and the moves after it: {}", m_str);
+                    break;
+                }
+            }
+        }
+    }
+
+    // applies a value that options::parse_setoption has already checked against the option's type and range
+    pub fn set_option(&mut self, option: &UciOption, value: OptionValue) {
+        self.stop_search();
+        match (option.name, value) {
+            (options::HASH, OptionValue::Spin(mb)) => self.tt = Arc::new(TranspositionTable::new(mb as usize)),
+            (options::CLEAR_HASH, _) => self.tt.clear(),
+            (options::THREADS, OptionValue::Spin(n)) => self.options.threads = n as usize,
+            (options::MULTIPV, OptionValue::Spin(n)) => self.options.multipv = n as usize,
+            (options::MOVE_OVERHEAD, OptionValue::Spin(ms)) => self.options.move_overhead = Duration::from_millis(ms as u64),
+            (options::CONTEMPT, OptionValue::Spin(cp)) => self.options.contempt = cp as i32,
+            (options::ANALYSIS_CONTEMPT, OptionValue::Combo(side)) => {
+                self.options.analysis_contempt = match side {
+                    "White" => search::AnalysisContempt::White,
+                    "Black" => search::AnalysisContempt::Black,
+                    "Both" => search::AnalysisContempt::Both,
+                    _ => search::AnalysisContempt::Off
+                };
+            },
+            (options::SKILL_LEVEL, OptionValue::Spin(level)) => self.options.skill_level = level as i32,
+            (options::DEBUG_LOG_FILE, OptionValue::String(path)) => {
+                self.log_file = None;
+                if !path.is_empty() {
+                    match OpenOptions::new().create(true).append(true).open(&path) {
+                        Ok(f) => self.log_file = Some(f),
+                        Err(e) => println!("ERROR: failed to open {}: {}", path, e)
+                    }
+                }
+            },
+            (options::NULL_MOVE_PRUNING, OptionValue::Check(v)) => self.options.null_move_pruning = v,
+            (options::NULL_MOVE_VERIFICATION, OptionValue::Check(v)) => self.options.null_move_verification = v,
+            (options::LATE_MOVE_REDUCTIONS, OptionValue::Check(v)) => self.options.late_move_reductions = v,
+            (options::FUTILITY_PRUNING, OptionValue::Check(v)) => self.options.futility_pruning = v,
+            _ => { } // options like Ponder that don't change anything on our side
+        }
+    }
+
+    // starts searching the current position on another thread, which prints bestmove when it's done
+    pub fn go(&mut self, limits: SearchLimits) {
+        self.stop_search();
+        self.signals.stop.store(false, Ordering::Relaxed);
+        self.signals.ponder.store(limits.ponder, Ordering::Relaxed);
+
+        let (pos, history, options) = (self.pos, self.history.clone(), self.options);
+        let (signals, tt) = (self.signals.clone(), Arc::clone(&self.tt));
+        self.search_thread = Some(thread::spawn(move || {
+            let result = search::search(&pos, &history, &limits, &options, &signals, &tt);
+            print_bestmove(&result);
+        }));
+    }
+
+    pub fn ponderhit(&self) {
+        self.signals.ponder.store(false, Ordering::Relaxed);
+    }
+
+    // tells the search thread to stop and waits for it to print its bestmove
+    pub fn stop_search(&mut self) {
+        if let Some(handle) = self.search_thread.take() {
+            self.signals.stop.store(true, Ordering::Relaxed);
+            handle.join().expect("search thread panicked");
+        }
+    }
+
+    pub fn log(&mut self, line: &str) {
+        if let Some(f) = self.log_file.as_mut() {
+            if writeln!(f, "{}", line).is_err() {
+                self.log_file = None; // losing the log shouldn't take the engine down with it
+            }
+        }
+    }
+}
+
+// whether a uci line has to stop a running search before we handle it
+// only commands that change or read the engine's state do; the rest, including blank lines and commands
+// we don't know (which uci says to ignore), leave the search running
+pub fn stops_search(line: &str) -> bool {
+    return matches!(line.split_whitespace().next(), Some("position" | "go" | "setoption" | "ucinewgame" | "DEBUG"));
+}
+
+fn print_bestmove(result: &SearchResult) {
+    match (result.best_move, result.pv.get(1)) {
+        (Some(m), Some(ponder_move)) => println!("bestmove {} ponder {}", m.to_uci(), ponder_move.to_uci()),
+        (Some(m), None) => println!("bestmove {}", m.to_uci()),
+        // checkmate or stalemate; uci has no other way to say we don't have a move
+        (None, _) => println!("bestmove 0000")
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use std::sync::mpsc;
+
+    #[test]
+    fn position_stops_infinite_search() {
+        // runs on another thread so a search that never stops fails the test instead of hanging it
+        let (done_tx, done_rx) = mpsc::channel();
+        thread::spawn(move || {
+            let mut engine = Engine::new();
+            engine.go(SearchLimits::from_uci("infinite".split_whitespace()));
+            thread::sleep(Duration::from_millis(50));
+            engine.set_position(utils::START_POSITION, std::iter::empty());
+            engine.go(SearchLimits::from_uci("infinite".split_whitespace()));
+            engine.stop_search();
+            done_tx.send(()).unwrap();
+        });
+        assert!(done_rx.recv_timeout(Duration::from_secs(10)).is_ok(), "position didn't stop the infinite search");
+    }
+
+    #[test]
+    fn blank_line_doesnt_stop_search() {
+        for line in ["", "xyzzy", "debug on", "register later"] {
+            assert!(!stops_search(line), "{:?} would stop the search", line);
+        }
+        for line in ["position startpos", "go infinite", "go perft 1", "setoption name Hash value 1", "ucinewgame", "DEBUG bench"] {
+            assert!(stops_search(line), "{:?} wouldn't stop the search", line);
+        }
+
+        let mut engine = Engine::new();
+        engine.go(SearchLimits::from_uci("infinite".split_whitespace()));
+        thread::sleep(Duration::from_millis(50));
+        assert!(!engine.search_thread.as_ref().unwrap().is_finished(), "infinite search ended on its own");
+        engine.stop_search();
+    }
+}
@@ -1,11 +1,33 @@
-use crate::types;
-use crate::types::{Direction, Color, PieceType, Square, Rank, File, CastlingRights, Board, Move, GamePhase};
+use crate::types::{Direction, Color, PieceType, Board, PackedMove, MoveList, GamePhase};
 use crate::bitboard;
 use crate::utils;
 use crate::move_search;
+use crate::move_order;
+use crate::search::SearchContext;
+use crate::tt::Bound;
 
 pub const EVAL_DRAW: i32 = 0;
 pub const EVAL_MATE: i32 = 1_000_000;
+pub const EVAL_INFINITY: i32 = 1_000_000_000;
+// rough piece values used to decide which captures are worth searching
+pub const PIECE_VALUES: [i32; 7] = [0, 940, 510, 330, 310, 100, 0]; // indexed by PieceType
+// how far below alpha a capture may leave us before quiescence search gives up on it
+const DELTA_MARGIN: i32 = 200;
+
+// null move pruning searches this many plies shallower than a normal move would be, plus one more per 6 plies of depth
+const NULL_MOVE_R: i32 = 2;
+const NULL_MOVE_MIN_DEPTH: i32 = 3;
+// from this depth on, a null move cutoff is only trusted once a reduced normal search confirms it
+const NULL_MOVE_VERIFICATION_DEPTH: i32 = 7;
+
+// late move reductions only kick in from this depth, and only for quiet moves ordered after the first few
+const LMR_MIN_DEPTH: i32 = 3;
+const LMR_MIN_MOVES: usize = 3;
+// at depth d, quiet moves are pruned if the static eval plus FUTILITY_MARGINS[d] can't reach alpha
+const FUTILITY_MARGINS: [i32; 4] = [0, 150, 300, 500];
+
+// mate scores are EVAL_MATE plus the number of plies left until MAX_PLY, so quicker mates score higher
+pub const MAX_PLY: i32 = 128;
 
 pub const KING_EVAL: [i32; 64] = [
     50050, 50050, 50050, 50000, 50000, 50000, 50050, 50050, 
@@ -84,108 +106,318 @@ pub const PAWN_EVAL: [i32; 64] = [
     90,  90, 100, 110, 110, 100,  90,  90   
 ];
 
-pub fn eval_move(b: &Board, m: Move, depth: i32) -> i32 {
-    if b.turn == Color::White {
-        let eval = eval_move_min(b, m, depth-1, -1_000_000_000, 1_000_000_000);
-        return eval;
-    } else {
-        let eval = eval_move_max(b, m, depth-1, -1_000_000_000, 1_000_000_000);
-        return -eval;
+// searches every root move to the given depth and returns the eval of the best one
+// root_moves is re-sorted so the best move comes first; the evals of the other moves are only upper bounds
+pub fn search_root(b: &Board, root_moves: &mut [(i32, PackedMove)], depth: i32, ctx: &mut SearchContext) -> i32 {
+    let mut alpha = -EVAL_INFINITY;
+    let beta = EVAL_INFINITY;
+    let mut best_eval = -EVAL_INFINITY;
+
+    for (i, root_move) in root_moves.iter_mut().enumerate() {
+        let m = root_move.1;
+        let eval = if i == 0 {
+            search_move(b, m, depth-1, 1, alpha, beta, ctx)
+        } else {
+            // assume the first move is best and just prove the others can't beat it
+            let null_window_eval = search_move(b, m, depth-1, 1, alpha, alpha+1, ctx);
+            if null_window_eval > alpha && !ctx.stopped {
+                search_move(b, m, depth-1, 1, alpha, beta, ctx)
+            } else {
+                null_window_eval
+            }
+        };
+        if ctx.stopped {
+            return best_eval;
+        }
+
+        root_move.0 = eval;
+        if eval > best_eval {
+            best_eval = eval;
+            alpha = alpha.max(eval);
+            ctx.pv.update(0, m);
+        }
     }
+
+    // the sort is stable so moves with equal evals keep their order
+    root_moves.sort_by_key(|k| -k.0);
+    return best_eval;
 }
 
-pub fn eval_move_max(b: &Board, m: Move, rem_depth: i32, alpha: i32, beta: i32) -> i32 {
-    let board = utils::apply_move(b, m);
+// applies m to b and searches the resulting position, which is recorded in the history while we do
+// returns the eval from the perspective of the side making the move
+fn search_move(b: &Board, m: PackedMove, depth: i32, ply: i32, alpha: i32, beta: i32, ctx: &mut SearchContext) -> i32 {
+    return search_child(b, &utils::apply_move(b, m), depth, ply, alpha, beta, ctx);
+}
+
+// same as search_move, for when the caller already applied the move
+fn search_child(b: &Board, board: &Board, depth: i32, ply: i32, alpha: i32, beta: i32, ctx: &mut SearchContext) -> i32 {
+    ctx.pv.clear(ply); // so the line of a node that returns early doesn't end up in our pv
+    ctx.history.push(board, utils::is_irreversible(b, board));
+    let eval = -negamax(board, depth, ply, -beta, -alpha, true, ctx);
+    ctx.history.pop();
+    return eval;
+}
 
-    if is_threefold_repetition(b) {
-        return EVAL_DRAW;
+// principal variation search from the perspective of the side to move
+// fail-soft: the returned eval may lie outside of [alpha, beta], in which case it's a bound on the real eval
+// allow_null is false right after a null move, since two null moves in a row would just search the same position again
+fn negamax(board: &Board, depth: i32, ply: i32, alpha: i32, beta: i32, allow_null: bool, ctx: &mut SearchContext) -> i32 {
+    if ctx.should_stop() {
+        return 0; // the result is thrown away anyway
     }
+    ctx.seldepth = ctx.seldepth.max(ply);
 
-    if rem_depth == 0 {
-        return eval_pos_quick(&board);
+    if ctx.history.is_repetition() || is_fifty_move_draw(board) {
+        return draw_eval(ply, ctx);
     }
 
-    let next_moves = move_search::calc_moves(&board);
+    if depth <= 0 || ply >= MAX_PLY {
+        return quiesce(board, ply, alpha, beta, ctx);
+    }
 
-    if next_moves.len() == 0 {
-        let test_board = utils::apply_null_move(&board);
-        if move_search::test_pmove_valid(&test_board) {
-            // test_pmove_valid returns true if there are no king captures
-            // in this case, that means we're stalemated
-            return EVAL_DRAW;
+    let tt_entry = ctx.tt.probe(board.hash, ply);
+    // no cutoffs in pv nodes (the ones searched with a full window), since they would cut the reported pv short
+    let pv_node = beta - alpha > 1;
+    if let Some(entry) = tt_entry {
+        if !pv_node && (entry.depth as i32) >= depth {
+            match entry.bound {
+                Bound::Exact => return entry.eval,
+                Bound::Lower if entry.eval >= beta => return entry.eval,
+                Bound::Upper if entry.eval <= alpha => return entry.eval,
+                _ => { }
+            }
+        }
+    }
+
+    let in_check = move_search::is_in_check(board);
+    // the static eval means little while in check, since we might be about to lose material or get mated
+    let static_eval = if in_check { -EVAL_INFINITY } else { eval_pos_rel(board) };
+
+    // null move pruning: let the opponent move twice in a row, and if a reduced search still can't bring us below beta,
+    // a real move would almost certainly fail high as well
+    // this is wrong in zugzwang, so we don't try it in check or when we only have pawns left, where zugzwang is common
+    if ctx.options.null_move_pruning && allow_null && !in_check && depth >= NULL_MOVE_MIN_DEPTH
+        && beta.abs() < EVAL_MATE && has_non_pawn_material(board) && static_eval >= beta {
+        let r = NULL_MOVE_R + depth/6;
+        let null_board = utils::apply_null_move(board);
+        ctx.history.push(&null_board, true); // positions before a null move can't repeat after it
+        let null_eval = -negamax(&null_board, depth-1-r, ply+1, -beta, -beta+1, false, ctx);
+        ctx.history.pop();
+        if ctx.stopped {
+            return 0;
+        }
+
+        if null_eval >= beta {
+            // a mate found after passing isn't a real mate, so we only return beta for those
+            let cutoff_eval = if null_eval >= EVAL_MATE { beta } else { null_eval };
+            if !ctx.options.null_move_verification || depth < NULL_MOVE_VERIFICATION_DEPTH {
+                return cutoff_eval;
+            }
+
+            // verification search: search this position normally (but reduced, and without null moves)
+            // to catch zugzwang positions that the material check missed
+            let verify_eval = negamax(board, depth-1-r, ply, beta-1, beta, false, ctx);
+            if ctx.stopped {
+                return 0;
+            }
+            if verify_eval >= beta {
+                return cutoff_eval;
+            }
+        }
+    }
+
+    let mut next_moves = move_search::calc_moves(board);
+
+    if next_moves.is_empty() {
+        if !in_check {
+            return draw_eval(ply, ctx); // stalemate
         } else {
-            // else we've checkmated
-            // to make it prefer slower mates, add in the "rem_depth"
-            return -EVAL_MATE - rem_depth;
+            // else we've been checkmated
+            // to make the winning side prefer quicker mates, subtract the ply
+            return -EVAL_MATE - (MAX_PLY - ply);
         }
     }
 
-    let mut new_alpha = alpha;
-    for next_move in next_moves.iter() {
-        let m_eval = eval_move_min(&board, *next_move, rem_depth-1, new_alpha, beta);
-        if m_eval >= beta {
-            return beta;
+    ctx.pv.clear(ply); // the verification search may have left a line here
+    let hash_move = tt_entry.and_then(|e| e.best_move);
+    move_order::order_moves(board, &mut next_moves, hash_move, &ctx.killer_moves[ply as usize], &ctx.history_scores);
+
+    let mut alpha = alpha;
+    let mut best_eval = -EVAL_INFINITY;
+    let mut best_move = None;
+    let mut bound = Bound::Upper;
+
+    // futility pruning: close to the horizon, a quiet move can't make up for a static eval this far below alpha
+    let futility_eval = if depth < FUTILITY_MARGINS.len() as i32 { static_eval + FUTILITY_MARGINS[depth as usize] } else { EVAL_INFINITY };
+    let futile = ctx.options.futility_pruning && !in_check && alpha.abs() < EVAL_MATE && futility_eval <= alpha;
+
+    for (i, next_move) in next_moves.iter().enumerate() {
+        let next_board = utils::apply_move(board, next_move);
+        let late = ctx.options.late_move_reductions && depth >= LMR_MIN_DEPTH && i >= LMR_MIN_MOVES;
+        // only quiet moves are pruned or reduced, and never ones that give check, since those are likely to be tactics
+        // checking for check is slow, so we only do it for moves that would otherwise be pruned or reduced
+        let reducible = i > 0 && (futile || late) && !in_check
+            && next_move.is_quiet() && !move_search::is_in_check(&next_board);
+
+        if futile && reducible {
+            best_eval = best_eval.max(futility_eval);
+            continue;
         }
-        if m_eval > new_alpha {
-            new_alpha = m_eval;
+
+        let eval = if i == 0 {
+            search_child(board, &next_board, depth-1, ply+1, alpha, beta, ctx)
+        } else {
+            // late move reductions: moves this far down the ordering rarely turn out best,
+            // so search them shallower first and only search them to full depth if they beat alpha
+            let reduction = if late && reducible {
+                ctx.reductions[depth.min(63) as usize][i.min(63)].clamp(0, depth-2)
+            } else {
+                0
+            };
+
+            // once we have a good move, try to prove the rest are worse with a null window,
+            // and only search them properly if that fails
+            let mut null_window_eval = search_child(board, &next_board, depth-1-reduction, ply+1, alpha, alpha+1, ctx);
+            if reduction > 0 && null_window_eval > alpha && !ctx.stopped {
+                null_window_eval = search_child(board, &next_board, depth-1, ply+1, alpha, alpha+1, ctx);
+            }
+            if null_window_eval > alpha && null_window_eval < beta && !ctx.stopped {
+                search_child(board, &next_board, depth-1, ply+1, alpha, beta, ctx)
+            } else {
+                null_window_eval
+            }
+        };
+        if ctx.stopped {
+            return 0;
+        }
+
+        if eval > best_eval {
+            best_eval = eval;
+            if eval > alpha {
+                best_move = Some(next_move);
+                ctx.pv.update(ply, next_move);
+                alpha = eval;
+                bound = Bound::Exact;
+                if eval >= beta {
+                    bound = Bound::Lower;
+                    if next_move.is_quiet() {
+                        move_order::add_killer_move(&mut ctx.killer_moves, ply, next_move);
+                        ctx.history_scores.add(board.turn, next_move, depth);
+                    }
+                    break;
+                }
+            }
         }
     }
 
-    return new_alpha;
+    ctx.tt.store(board.hash, best_move, best_eval, depth, bound, ply);
+    return best_eval;
 }
 
-pub fn eval_move_min(b: &Board, m: Move, rem_depth: i32, alpha: i32, beta: i32) -> i32 {
-    let board = utils::apply_move(b, m);
+// at the horizon we only follow captures and promotions, until the position is quiet enough to trust the static eval
+// the side to move may "stand pat" on the static eval instead of capturing, since it isn't forced to capture
+// in check it is forced to move, and may be mated, so there we search every evasion and don't stand pat
+fn quiesce(board: &Board, ply: i32, alpha: i32, beta: i32, ctx: &mut SearchContext) -> i32 {
+    if ctx.should_stop() {
+        return 0; // the result is thrown away anyway
+    }
+    ctx.seldepth = ctx.seldepth.max(ply);
 
-    if is_threefold_repetition(b) {
-        return EVAL_DRAW;
+    if ply >= MAX_PLY {
+        return eval_pos_rel(board);
     }
 
-    if rem_depth == 0 {
-        return eval_pos_quick(&board);
+    let in_check = move_search::is_in_check(board);
+    let stand_pat = if in_check { -EVAL_INFINITY } else { eval_pos_rel(board) };
+    if stand_pat >= beta {
+        return stand_pat;
     }
 
-    let next_moves = move_search::calc_moves(&board);
+    // delta pruning: if even winning a queen can't bring us back to alpha, no capture will
+    if !in_check && stand_pat + PIECE_VALUES[PieceType::Queen as usize] + DELTA_MARGIN <= alpha {
+        return stand_pat;
+    }
 
-    if next_moves.len() == 0 {
-        let test_board = utils::apply_null_move(&board);
-        if move_search::test_pmove_valid(&test_board) {
-            // test_pmove_valid returns true if there are no king captures
-            // in this case, that means we're stalemated
-            return EVAL_DRAW;
-        } else {
-            // else we've checkmated
-            // to make it prefer quicker mates, add in the "rem_depth"
-            return EVAL_MATE + rem_depth;
+    let next_moves = if in_check {
+        let mut evasions = move_search::calc_moves(board);
+        if evasions.is_empty() {
+            return -EVAL_MATE - (MAX_PLY - ply);
         }
-    }
+        move_order::order_moves(board, &mut evasions, None, &ctx.killer_moves[ply as usize], &ctx.history_scores);
+        evasions
+    } else {
+        calc_noisy_moves(board)
+    };
 
-    let mut new_beta = beta;
+    let mut alpha = alpha.max(stand_pat);
+    let mut best_eval = stand_pat;
     for next_move in next_moves.iter() {
-        let m_eval = eval_move_max(&board, *next_move, rem_depth-1, alpha, new_beta);
-        if m_eval <= alpha {
-            return alpha;
+        let gain = material_gain(board, next_move);
+        if !in_check && stand_pat + gain + DELTA_MARGIN <= alpha {
+            continue; // this capture can't win enough material to matter
+        }
+        let eval = -quiesce(&utils::apply_move(board, next_move), ply+1, -beta, -alpha, ctx);
+        if ctx.stopped {
+            return 0;
         }
-        if m_eval < new_beta {
-            new_beta = m_eval;
+        if eval > best_eval {
+            best_eval = eval;
+            if eval > alpha {
+                alpha = eval;
+                if eval >= beta {
+                    break;
+                }
+            }
         }
     }
 
-    return new_beta;
+    return best_eval;
 }
 
-fn is_threefold_repetition(b: &Board) -> bool {
-    // check for threefold repetition
-    // this method is imperfect but so is this engine :)
-    // it's good enough for now
-    let mut rep_count = 0;
-    let prev_all_piece = b.all_piece_history[(b.all_ptr-1) % 16];
-    for i in 0usize..16usize {
-        if b.all_piece_history[i] == prev_all_piece {
-            rep_count = rep_count+1;
-        }
+// returns the legal captures and promotions, ordered by mvv-lva
+fn calc_noisy_moves(board: &Board) -> MoveList {
+    let mut noisy_moves = move_search::calc_moves(board);
+    for sm in noisy_moves.scored_moves_mut().iter_mut() {
+        let gain = material_gain(board, sm.m);
+        sm.score = if gain > 0 { move_order::mvv_lva_score(board, sm.m) + gain } else { 0 };
+    }
+    noisy_moves.retain(|sm| sm.score > 0);
+    noisy_moves.sort_by_score();
+    return noisy_moves;
+}
+
+// how much material a move wins by capturing and promoting
+fn material_gain(board: &Board, m: PackedMove) -> i32 {
+    let mut gain = PIECE_VALUES[utils::get_captured_piece(board, m) as usize];
+    if m.is_promotion() {
+        gain += PIECE_VALUES[m.promote_type() as usize] - PIECE_VALUES[PieceType::Pawn as usize];
     }
-    return rep_count >= 3;
+    return gain;
+}
+
+// contempt makes the side to move at the root see a draw as slightly worse than equal, so it avoids drawing lines
+// the side to move is the root side on even plies
+fn draw_eval(ply: i32, ctx: &SearchContext) -> i32 {
+    return if ply % 2 == 0 { EVAL_DRAW - ctx.contempt } else { EVAL_DRAW + ctx.contempt };
+}
+
+fn has_non_pawn_material(b: &Board) -> bool {
+    let pieces = if b.turn == Color::White { &b.white_bitboard_pieces } else { &b.black_bitboard_pieces };
+    return (pieces.queens | pieces.rooks | pieces.bishops | pieces.knights) != 0;
+}
+
+// after 100 plies without a pawn move or capture the game is drawn, unless the last move delivered checkmate
+fn is_fifty_move_draw(b: &Board) -> bool {
+    if b.halfmove_clock < 100 {
+        return false;
+    }
+    return !(move_search::is_in_check(b) && move_search::calc_moves(b).is_empty());
+}
+
+// static eval from the perspective of the side to move
+fn eval_pos_rel(b: &Board) -> i32 {
+    let eval = eval_pos_quick(b);
+    return if b.turn == Color::White { eval } else { -eval };
 }
 
 fn eval_pos_quick(b: &Board) -> i32 {
@@ -1,95 +1,96 @@
+// we prefer explicit returns throughout the codebase
+#![allow(clippy::needless_return)]
+
 mod types;
 mod bitboard;
+mod attacks;
 mod utils;
 mod eval;
 mod move_search;
+mod move_order;
+mod zobrist;
+mod perft;
+mod search;
+mod tt;
+mod options;
+mod engine;
 
 use std::io;
-use rand;
-use rand::seq::SliceRandom;
+use std::time::Instant;
 
 fn main() -> io::Result<()> {
-    let mut pos = utils::START_POSITION;
+    let mut engine = engine::Engine::new();
 
     // UCI parsing
     loop {
         let mut line = String::new();
-        io::stdin().read_line(&mut line)?;
+        if io::stdin().read_line(&mut line)? == 0 {
+            line = String::from("quit"); // stdin was closed
+        }
         line = line.trim().to_string();
+        engine.log(&line);
+
+        // the search runs on its own thread so we can still answer isready, stop, ponderhit and quit while it does
+        // commands that change state the search is using stop it first; blank and unknown lines leave it alone
+        // just waiting for it would never return during go infinite or go ponder, and we'd never read the stop
+        if engine::stops_search(&line) {
+            engine.stop_search();
+        }
 
         if line == "quit" {
+            engine.stop_search();
             break;
         } else if line == "uci" {
             println!("id name Goldychess v0.2");
             println!("id author Michael Goldstein");
+            for option in options::OPTIONS.iter() {
+                println!("{}", option.to_uci());
+            }
             println!("uciok");
         } else if line == "isready" {
             println!("readyok");
+        } else if line == "stop" {
+            engine.stop_search();
+        } else if line == "ponderhit" {
+            engine.ponderhit();
+        } else if line == "ucinewgame" {
+            engine.new_game();
+        } else if line.starts_with("setoption") {
+            let (option, value) = match options::parse_setoption(&line) {
+                Ok(o) => o,
+                Err(e) => {
+                    println!("ERROR: {}", e);
+                    continue;
+                }
+            };
+            engine.set_option(option, value);
         } else if line.starts_with("position fen") {
-            println!("ERROR: parsing fen not implemented");
+            let mut tokens = line.split_whitespace().skip(2);
+            let fen_fields: Vec<&str> = tokens.by_ref().take_while(|&t| t != "moves").collect();
+            match types::Board::from_fen(&fen_fields.join(" ")) {
+                // take_while has already consumed "moves", so whatever remains are the moves
+                Ok(b) => engine.set_position(b, tokens),
+                Err(e) => println!("ERROR: failed to parse fen: {}", e)
+            }
         } else if line.starts_with("position startpos") {
-            pos = utils::START_POSITION; // reset to startpos
             let mut tokens = line.split_whitespace().skip(2);
-            match tokens.next() {
-                Some(x) => {
-                    if x == "moves" {
-                        for m_str in tokens {
-                            match (types::Move::from_uci(m_str)) {
-                                Some(m) => {
-                                    pos = utils::apply_move(&pos, m);
-                                },
-                                _ => {
-                                    println!("ERROR: failed to parse move: {}", m_str);
-                                    continue;
-                                }
-                            }
-                        }
+            let moves: Vec<&str> = if tokens.next() == Some("moves") { tokens.collect() } else { Vec::new() };
+            engine.set_position(utils::START_POSITION, moves.into_iter());
+        } else if line.starts_with("go perft") {
+            match line.split_whitespace().nth(2).map(|d| d.parse::<u32>()) {
+                Some(Ok(depth)) => {
+                    // same output format as stockfish so existing perft comparison tools work
+                    let counts = perft::divide(&engine.pos, depth);
+                    for (m, nodes) in counts.iter() {
+                        println!("{}: {}", m.to_uci(), nodes);
                     }
+                    println!("\nNodes searched: {}", counts.iter().map(|(_, n)| n).sum::<u64>());
                 },
-                _ => {
-                    continue;
-                }
+                _ => println!("ERROR: failed to parse perft depth")
             }
         } else if line.starts_with("go") {
-            let n = "go".len();
-            let tokens = line[n..].split_whitespace();
-            let mut moves = move_search::calc_moves(&pos);
-
-            moves.shuffle(&mut rand::thread_rng()); // shuffle to make our move choices a little more interesting
-
-            let mut moves_with_eval = Vec::<(i32, types::Move)>::new();
-            let depth = 6;
-    
-            for m in moves.iter() {
-                let eval = eval::eval_move(&pos, *m, depth);
-                moves_with_eval.push((eval, *m));
-            }
-            moves_with_eval.sort_by_key(|k| k.0);
-
-            for (e, m) in moves_with_eval.iter() {
-                if *e >= eval::EVAL_MATE {
-                    let moves_to_mate = (depth - (*e - eval::EVAL_MATE))/2;
-                    println!("info depth {} score mate {} pv {}", depth, moves_to_mate, m.to_uci());
-                } else if *e <= -eval::EVAL_MATE {
-                    let moves_to_mate = -(depth + (*e + eval::EVAL_MATE))/2;
-                    println!("info depth {} score mate {} pv {}", depth, moves_to_mate, m.to_uci());
-                } else {
-                    println!("info depth {} score cp {} pv {}", depth, e, m.to_uci());
-                }
-            }
-
-            let best_pv = moves_with_eval.into_iter().last();
-
-            match best_pv {
-                Some((e, m)) => {
-                    println!("bestmove {}", m.to_uci());
-                },
-                _ => println!("ERROR: no move found")
-            }
-
-        } else if line.starts_with("time") {
-
-        } else if line.starts_with("otim") {
+            engine.go(search::SearchLimits::from_uci(line.split_whitespace().skip(1)));
+        } else if line.starts_with("time") || line.starts_with("otim") {
 
         } else if line.starts_with("DEBUG") {
             let mut tokens = line.split_whitespace().skip(1);
@@ -97,50 +98,77 @@ fn main() -> io::Result<()> {
                 Some(x) => {
                     match x {
                         "showmoves" => {
-                            let moves = move_search::calc_moves(&pos);
+                            let moves = move_search::calc_moves(&engine.pos);
                             for m in moves.iter() {
                                 println!("{:?}", m);
                             }
                         },
                         "showpmoves" => {
-                            let moves = move_search::calc_pmoves(&pos);
+                            let moves = move_search::calc_pmoves(&engine.pos);
                             for m in moves.iter() {
                                 println!("{:?}", m);
                             }
                         },
+                        "divide" => {
+                            match tokens.next().map(|d| d.parse::<u32>()) {
+                                Some(Ok(depth)) => {
+                                    let start = Instant::now();
+                                    let counts = perft::divide(&engine.pos, depth);
+                                    let elapsed = start.elapsed();
+                                    for (m, nodes) in counts.iter() {
+                                        println!("{} {}", m.to_uci(), nodes);
+                                    }
+                                    let total = counts.iter().map(|(_, n)| n).sum::<u64>();
+                                    let nps = (total as f64 / elapsed.as_secs_f64().max(0.001)) as u64;
+                                    println!("moves {} nodes {} time {} nps {}", counts.len(), total, elapsed.as_millis(), nps);
+                                },
+                                _ => println!("ERROR: failed to parse divide depth")
+                            }
+                        },
+                        "bench" => {
+                            match tokens.next().map(|d| d.parse::<i32>()) {
+                                Some(Ok(depth)) => search::bench(depth, &engine.options),
+                                Some(Err(_)) => println!("ERROR: failed to parse bench depth"),
+                                None => search::bench(search::BENCH_DEPTH, &engine.options)
+                            }
+                        },
                         "showboard" => {
-                            pos.pretty_print();
+                            engine.pos.pretty_print();
                         },
-                        "showallpiecehistory" => {
-                            for i in 0..16 {
-                                let ptr = (pos.all_ptr + i) % 16;
-                                println!("\nall_piece_history[{}]", ptr);
-                                bitboard::bitboard_pretty_print(pos.all_piece_history[ptr]);
+                        "showfen" => {
+                            println!("{}", engine.pos.to_fen());
+                        },
+                        "showhistory" => {
+                            for entry in engine.history.entries.iter() {
+                                println!("{:016X}{}", entry.hash, if entry.irreversible { " (irreversible)" } else { "" });
                             }
-                        }
+                        },
                         "printboard" => {
-                            println!("{:?}", pos);
+                            println!("{:?}", engine.pos);
+                        },
+                        "hash" => {
+                            println!("{:016X}", engine.pos.hash);
                         },
                         "color" => {
-                            println!("{:?}", pos.turn);
+                            println!("{:?}", engine.pos.turn);
                         },
                         "showbitboards" => {
                             println!("White A/K/Q/R/B/N/P:");
-                            println!("{:016X}", pos.white_bitboard_pieces.all);
-                            println!("{:016X}", pos.white_bitboard_pieces.king);
-                            println!("{:016X}", pos.white_bitboard_pieces.queens);
-                            println!("{:016X}", pos.white_bitboard_pieces.rooks);
-                            println!("{:016X}", pos.white_bitboard_pieces.bishops);
-                            println!("{:016X}", pos.white_bitboard_pieces.knights);
-                            println!("{:016X}", pos.white_bitboard_pieces.pawns);
+                            println!("{:016X}", engine.pos.white_bitboard_pieces.all);
+                            println!("{:016X}", engine.pos.white_bitboard_pieces.king);
+                            println!("{:016X}", engine.pos.white_bitboard_pieces.queens);
+                            println!("{:016X}", engine.pos.white_bitboard_pieces.rooks);
+                            println!("{:016X}", engine.pos.white_bitboard_pieces.bishops);
+                            println!("{:016X}", engine.pos.white_bitboard_pieces.knights);
+                            println!("{:016X}", engine.pos.white_bitboard_pieces.pawns);
                             println!("Black A/K/Q/R/B/N/P:");
-                            println!("{:016X}", pos.black_bitboard_pieces.all);
-                            println!("{:016X}", pos.black_bitboard_pieces.king);
-                            println!("{:016X}", pos.black_bitboard_pieces.queens);
-                            println!("{:016X}", pos.black_bitboard_pieces.rooks);
-                            println!("{:016X}", pos.black_bitboard_pieces.bishops);
-                            println!("{:016X}", pos.black_bitboard_pieces.knights);
-                            println!("{:016X}", pos.black_bitboard_pieces.pawns);
+                            println!("{:016X}", engine.pos.black_bitboard_pieces.all);
+                            println!("{:016X}", engine.pos.black_bitboard_pieces.king);
+                            println!("{:016X}", engine.pos.black_bitboard_pieces.queens);
+                            println!("{:016X}", engine.pos.black_bitboard_pieces.rooks);
+                            println!("{:016X}", engine.pos.black_bitboard_pieces.bishops);
+                            println!("{:016X}", engine.pos.black_bitboard_pieces.knights);
+                            println!("{:016X}", engine.pos.black_bitboard_pieces.pawns);
                         },
                         _ => {
                             continue;
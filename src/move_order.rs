@@ -0,0 +1,95 @@
+use crate::types::{Color, PieceType, Board, PackedMove, MoveList};
+use crate::eval;
+use crate::utils;
+
+// moves are searched in order of these scores, highest first
+const HASH_MOVE_SCORE: i32 = 1_000_000;
+const CAPTURE_SCORE: i32 = 100_000;
+const PROMOTION_SCORE: i32 = 90_000;
+const KILLER_SCORES: [i32; 2] = [80_000, 79_000];
+// history scores are kept below this so quiet moves never get ordered before killers
+const MAX_HISTORY_SCORE: i32 = 50_000;
+
+// quiet moves that caused a beta cutoff at each ply; sibling positions often have the same refutation
+pub type KillerMoves = [[Option<PackedMove>; 2]; eval::MAX_PLY as usize];
+
+// how often a quiet move (by color, from square and to square) caused a beta cutoff, weighted by depth
+pub struct HistoryScores {
+    scores: [[[i32; 64]; 64]; 2]
+}
+
+impl HistoryScores {
+    pub fn new() -> HistoryScores {
+        return HistoryScores { scores: [[[0; 64]; 64]; 2] };
+    }
+
+    pub fn get(&self, c: Color, m: PackedMove) -> i32 {
+        return self.scores[c as usize][m.from_square().to_index()][m.to_square().to_index()];
+    }
+
+    pub fn add(&mut self, c: Color, m: PackedMove, depth: i32) {
+        let score = &mut self.scores[c as usize][m.from_square().to_index()][m.to_square().to_index()];
+        *score += depth * depth;
+        if *score >= MAX_HISTORY_SCORE {
+            // age every entry so the scores stay bounded and recent cutoffs count for more
+            for from in self.scores.iter_mut().flat_map(|c| c.iter_mut()) {
+                for s in from.iter_mut() {
+                    *s /= 2;
+                }
+            }
+        }
+    }
+}
+
+pub fn new_killer_moves() -> KillerMoves {
+    return [[None; 2]; eval::MAX_PLY as usize];
+}
+
+pub fn add_killer_move(killers: &mut KillerMoves, ply: i32, m: PackedMove) {
+    let slots = &mut killers[ply as usize];
+    if slots[0] != Some(m) {
+        slots[1] = slots[0];
+        slots[0] = Some(m);
+    }
+}
+
+// most valuable victim, least valuable attacker: prefer winning big pieces, and with cheap pieces
+pub fn mvv_lva_score(b: &Board, m: PackedMove) -> i32 {
+    let victim = utils::get_captured_piece(b, m);
+    if victim == PieceType::Null {
+        return 0;
+    }
+    let (attacker, _) = b.get_piece(m.from_square().to_index());
+    return eval::PIECE_VALUES[victim as usize] * 16 - eval::PIECE_VALUES[attacker as usize];
+}
+
+// scores every move and sorts them best first:
+// the hash move, then captures by mvv-lva, then promotions, then killer moves, then quiet moves by history score
+pub fn order_moves(b: &Board, moves: &mut MoveList, hash_move: Option<PackedMove>, killers: &[Option<PackedMove>; 2], history: &HistoryScores) {
+    for sm in moves.scored_moves_mut().iter_mut() {
+        sm.score = score_move(b, sm.m, hash_move, killers, history);
+    }
+    moves.sort_by_score();
+}
+
+fn score_move(b: &Board, m: PackedMove, hash_move: Option<PackedMove>, killers: &[Option<PackedMove>; 2], history: &HistoryScores) -> i32 {
+    if Some(m) == hash_move {
+        return HASH_MOVE_SCORE;
+    }
+
+    let promotion_value = if m.is_promotion() { eval::PIECE_VALUES[m.promote_type() as usize] } else { 0 };
+    if m.is_capture() {
+        return CAPTURE_SCORE + mvv_lva_score(b, m) + promotion_value;
+    }
+    if promotion_value > 0 {
+        return PROMOTION_SCORE + promotion_value;
+    }
+
+    for (i, &killer) in killers.iter().enumerate() {
+        if Some(m) == killer {
+            return KILLER_SCORES[i];
+        }
+    }
+
+    return history.get(b.turn, m);
+}
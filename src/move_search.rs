@@ -1,23 +1,177 @@
 use crate::types;
-use crate::types::{Direction, Color, PieceType, Square, Rank, File, CastlingRights, Board, Move};
+use crate::types::{Direction, Color, Square, Board, PackedMove, MoveList};
 use crate::bitboard;
-use crate::utils;
+use crate::bitboard::Bitboard;
+use crate::attacks;
 
-pub fn calc_moves(b : &Board) -> Vec<Move> {
-    let mut vec = Vec::<Move>::new();
-    for &m in calc_pmoves(b).iter() {
-        let test_board = utils::apply_move(b, m);
-        if test_pmove_valid(&test_board) {
-            vec.push(m);
+// generates only legal moves: instead of trying every pmove and checking the king afterwards, we find the pieces
+// giving check and the pieces pinned to our king first, and only let each piece move where it keeps the king safe
+pub fn calc_moves(b: &Board) -> MoveList {
+    let mut moves = MoveList::new();
+
+    let (pieces, enemy_pieces) = if b.turn == Color::White {
+        (b.white_bitboard_pieces, b.black_bitboard_pieces)
+    } else {
+        (b.black_bitboard_pieces, b.white_bitboard_pieces)
+    };
+    let enemy_color = b.turn.opposite();
+    let all_bitboard = pieces.all | enemy_pieces.all;
+    let king_idx = pieces.king.trailing_zeros() as usize;
+    let checkers = checkers(b);
+
+    // the king can go to any square the enemy doesn't attack
+    // the king itself is left out of the blockers, or it would hide the squares behind it from a slider checking it
+    let mut bb = attacks::king_attacks(king_idx) & !pieces.all;
+    while bb != 0 {
+        let to_idx = bb.trailing_zeros() as usize;
+        if attackers_with_blockers(&enemy_pieces, enemy_color, to_idx, all_bitboard ^ pieces.king) == 0 {
+            push_moves(&mut moves, king_idx, 1u64 << to_idx, enemy_pieces.all);
+        }
+        bb &= bb - 1;
+    }
+
+    // in double check only the king can move
+    if checkers.count_ones() > 1 {
+        return moves;
+    }
+
+    // out of check, every other move has to capture the checker or block it
+    let check_mask = if checkers != 0 { checkers | attacks::between(king_idx, checkers.trailing_zeros() as usize) } else { !0u64 };
+
+    // a piece is pinned if it's the only piece between our king and an enemy slider that could otherwise reach it
+    let mut pinned = 0u64;
+    let mut snipers = (attacks::rook_attacks(king_idx, enemy_pieces.all) & (enemy_pieces.rooks | enemy_pieces.queens))
+        | (attacks::bishop_attacks(king_idx, enemy_pieces.all) & (enemy_pieces.bishops | enemy_pieces.queens));
+    while snipers != 0 {
+        let blockers = attacks::between(king_idx, snipers.trailing_zeros() as usize) & all_bitboard;
+        if blockers.count_ones() == 1 {
+            pinned |= blockers & pieces.all;
+        }
+        snipers &= snipers - 1;
+    }
+
+    let targets = MoveTargets {
+        allowed: check_mask & !pieces.all,
+        enemy: enemy_pieces.all,
+        pinned,
+        king_idx
+    };
+
+    // pawns
+    {
+        let (promotion_rank, double_push_rank) = if b.turn == Color::White { (bitboard::RANK_8, bitboard::RANK_3) } else { (bitboard::RANK_1, bitboard::RANK_6) };
+        let forward = |bb: Bitboard| if b.turn == Color::White { bb << 8 } else { bb >> 8 };
+        let mut pawns_bb = pieces.pawns;
+        while pawns_bb != 0 {
+            let from_idx = pawns_bb.trailing_zeros() as usize;
+            let push = forward(1u64 << from_idx) & !all_bitboard;
+            let double_push = forward(push & double_push_rank) & !all_bitboard;
+            let captures = attacks::pawn_attacks(b.turn, from_idx) & enemy_pieces.all;
+            let from_square = Square::from_index(from_idx as u32).unwrap();
+            let mut bb = (push | captures) & targets.for_piece(from_idx);
+            while bb != 0 {
+                let to_idx = bb.trailing_zeros();
+                let promotes = ((1u64 << to_idx) & promotion_rank) != 0;
+                let capture = ((1u64 << to_idx) & enemy_pieces.all) != 0;
+                push_pawn_move(&mut moves, from_square, Square::from_index(to_idx).unwrap(), promotes, capture);
+                bb &= bb - 1;
+            }
+            if (double_push & targets.for_piece(from_idx)) != 0 {
+                moves.push(PackedMove::new(from_square, Square::from_index(double_push.trailing_zeros()).unwrap(), PackedMove::DOUBLE_PAWN_PUSH));
+            }
+            pawns_bb &= pawns_bb - 1;
+        }
+
+        // enpassant captures take a pawn from a square other than the one we move to, so the masks above don't
+        // cover them, and taking two pawns off one rank can expose our king to a rook along it
+        // instead we check each one by taking both pawns off the board and looking for attacks on our king
+        if b.enpassant_files != bitboard::EMPTY_BITRANK {
+            let file_idx = b.enpassant_files.trailing_zeros() as usize;
+            let (to_idx, captured_idx) = if b.turn == Color::White { (40 + file_idx, 32 + file_idx) } else { (16 + file_idx, 24 + file_idx) };
+            let mut enemy_after = enemy_pieces;
+            enemy_after.pawns &= !(1u64 << captured_idx);
+            let mut bb = attacks::pawn_attacks(enemy_color, to_idx) & pieces.pawns;
+            while bb != 0 {
+                let from_idx = bb.trailing_zeros() as usize;
+                let all_after = (all_bitboard ^ (1u64 << from_idx) ^ (1u64 << captured_idx)) | (1u64 << to_idx);
+                if attackers_with_blockers(&enemy_after, enemy_color, king_idx, all_after) == 0 {
+                    moves.push(PackedMove::new(Square::from_index(from_idx as u32).unwrap(), Square::from_index(to_idx as u32).unwrap(), PackedMove::ENPASSANT_CAPTURE));
+                }
+                bb &= bb - 1;
+            }
+        }
+    }
+
+    push_legal_piece_moves(&mut moves, pieces.knights, &targets, attacks::knight_attacks);
+    push_legal_piece_moves(&mut moves, pieces.bishops, &targets, |sq| attacks::bishop_attacks(sq, all_bitboard));
+    push_legal_piece_moves(&mut moves, pieces.rooks, &targets, |sq| attacks::rook_attacks(sq, all_bitboard));
+    push_legal_piece_moves(&mut moves, pieces.queens, &targets, |sq| attacks::queen_attacks(sq, all_bitboard));
+
+    // castling: the king can't castle out of, through or into check
+    if checkers == 0 {
+        let (can_castle_long, can_castle_short) = if b.turn == Color::White {
+            (b.castling_rights.white_long, b.castling_rights.white_short)
+        } else {
+            (b.castling_rights.black_long, b.castling_rights.black_short)
+        };
+        let castles = [
+            (can_castle_long, bitboard::LONG_CASTLE_BITBOARD, Square::D1, Square::C1, PackedMove::QUEEN_CASTLE),
+            (can_castle_short, bitboard::SHORT_CASTLE_BITBOARD, Square::F1, Square::G1, PackedMove::KING_CASTLE)
+        ];
+        for &(can_castle, path, through_sq, to_sq, flags) in castles.iter() {
+            let (through_sq, to_sq) = (through_sq.rel(b.turn), to_sq.rel(b.turn));
+            if can_castle && (all_bitboard & bitboard::get_bitboard_rel(path, b.turn)) == 0
+                && !is_square_attacked(b, through_sq.to_index(), enemy_color) && !is_square_attacked(b, to_sq.to_index(), enemy_color) {
+                moves.push(PackedMove::new(Square::E1.rel(b.turn), to_sq, flags));
+            }
+        }
+    }
+
+    return moves;
+}
+
+// where the pieces other than the king may move in a position
+struct MoveTargets {
+    allowed: Bitboard, // empty or enemy squares that also deal with any check
+    enemy: Bitboard, // moves to these squares are captures
+    pinned: Bitboard,
+    king_idx: usize
+}
+
+impl MoveTargets {
+    // a pinned piece can only move along the line through it and our king
+    fn for_piece(&self, from_idx: usize) -> Bitboard {
+        if (self.pinned & (1u64 << from_idx)) != 0 {
+            return self.allowed & attacks::line(self.king_idx, from_idx);
         }
+        return self.allowed;
     }
-    return vec;
-    //return calc_pmoves(b);
 }
 
-pub fn calc_pmoves(b: &Board) -> Vec<Move> {
-    let mut capscasts = Vec::<Move>::new(); // captures and castles
-    let mut moves = Vec::<Move>::new(); // other moves
+// pushes the legal moves of every piece on a square of pieces_bb, given the squares a piece attacks from each square
+fn push_legal_piece_moves(moves: &mut MoveList, pieces_bb: Bitboard, targets: &MoveTargets, piece_attacks: impl Fn(usize) -> Bitboard) {
+    let mut bb = pieces_bb;
+    while bb != 0 {
+        let from_idx = bb.trailing_zeros() as usize;
+        push_moves(moves, from_idx, piece_attacks(from_idx) & targets.for_piece(from_idx), targets.enemy);
+        bb &= bb - 1;
+    }
+}
+
+// pushes a move from from_idx to each square of to_bitboard, flagged as a capture if the square is in enemy_bitboard
+fn push_moves(moves: &mut MoveList, from_idx: usize, to_bitboard: Bitboard, enemy_bitboard: Bitboard) {
+    let from_square = Square::from_index(from_idx as u32).unwrap();
+    let mut bb = to_bitboard;
+    while bb != 0 {
+        let to_idx = bb.trailing_zeros();
+        let flags = if ((1u64 << to_idx) & enemy_bitboard) != 0 { PackedMove::CAPTURE } else { PackedMove::QUIET };
+        moves.push(PackedMove::new(from_square, Square::from_index(to_idx).unwrap(), flags));
+        bb &= bb - 1;
+    }
+}
+
+pub fn calc_pmoves(b: &Board) -> MoveList {
+    let mut moves = MoveList::new();
 
     let pieces = if b.turn == Color::White { b.white_bitboard_pieces } else { b.black_bitboard_pieces };
     let enemy_pieces = if b.turn == Color::White { b.black_bitboard_pieces } else { b.white_bitboard_pieces };
@@ -35,7 +189,7 @@ pub fn calc_pmoves(b: &Board) -> Vec<Move> {
         | enemy_pieces.knights
         | enemy_pieces.pawns;
     let enemy_bitboard_rel = bitboard::get_bitboard_rel(enemy_bitboard, b.turn);
-    let enemy_bitboard_rel_enpassant = enemy_bitboard_rel | ((b.enpassant_files as u64) << 8*5); // to generate enpassant captures, we make the bitboard pawns look at for captures have synthetic pawns on the enpassant capture rank (rank 6)
+    let enemy_bitboard_rel_enpassant = enemy_bitboard_rel | ((b.enpassant_files as u64) << (8*5)); // to generate enpassant captures, we make the bitboard pawns look at for captures have synthetic pawns on the enpassant capture rank (rank 6)
     let all_bitboard_rel = friendly_bitboard_rel | enemy_bitboard_rel;
 
     // pawns
@@ -49,21 +203,21 @@ pub fn calc_pmoves(b: &Board) -> Vec<Move> {
                 let move_bb = bitboard::bitboard_from_index(idx);
                 let to_square = Square::from_index(idx).unwrap();
                 let from_square = to_square.slide(Direction::S, 1).unwrap();
-                let promote_type = if (move_bb & bitboard::RANK_8) != 0 { PieceType::Queen } else { PieceType::Null };
-                moves.push(Move {from_square: from_square.rel(b.turn), to_square: to_square.rel(b.turn), promote_type: promote_type});
-                bb = bb & !move_bb;
+                let promotes = (move_bb & bitboard::RANK_8) != 0;
+                push_pawn_move(&mut moves, from_square.rel(b.turn), to_square.rel(b.turn), promotes, false);
+                bb &= !move_bb;
             }
             // if a pawn can move 1 square, it might be able to move 2
             {
-                let moves2_bitboard = bitboard::slide((moves_bitboard & bitboard::RANK_3), Direction::N, 1) & !all_bitboard_rel;
+                let moves2_bitboard = bitboard::slide(moves_bitboard & bitboard::RANK_3, Direction::N, 1) & !all_bitboard_rel;
                 bb = moves2_bitboard;
                 while bb != 0 {
                     let idx = bb.trailing_zeros();
                     let move_bb = bitboard::bitboard_from_index(idx);
                     let to_square = Square::from_index(idx).unwrap();
                     let from_square = to_square.slide(Direction::S, 2).unwrap();
-                    moves.push(Move {from_square: from_square.rel(b.turn), to_square: to_square.rel(b.turn), promote_type: PieceType::Null});
-                    bb = bb & !move_bb;
+                    moves.push(PackedMove::new(from_square.rel(b.turn), to_square.rel(b.turn), PackedMove::DOUBLE_PAWN_PUSH));
+                    bb &= !move_bb;
                 }
             }
         }
@@ -76,9 +230,8 @@ pub fn calc_pmoves(b: &Board) -> Vec<Move> {
                 let move_bb = bitboard::bitboard_from_index(idx);
                 let to_square = Square::from_index(idx).unwrap();
                 let from_square = to_square.slide(Direction::SE, 1).unwrap();
-                let promote_type = if (move_bb & bitboard::RANK_8) != 0 { PieceType::Queen } else { PieceType::Null };
-                moves.push(Move {from_square: from_square.rel(b.turn), to_square: to_square.rel(b.turn), promote_type: promote_type});
-                bb = bb & !move_bb;
+                push_pawn_capture(&mut moves, from_square.rel(b.turn), to_square.rel(b.turn), move_bb, enemy_bitboard_rel);
+                bb &= !move_bb;
             }
         }
         // pawn capturing NE
@@ -90,267 +243,106 @@ pub fn calc_pmoves(b: &Board) -> Vec<Move> {
                 let move_bb = bitboard::bitboard_from_index(idx);
                 let to_square = Square::from_index(idx).unwrap();
                 let from_square = to_square.slide(Direction::SW, 1).unwrap();
-                let promote_type = if (move_bb & bitboard::RANK_8) != 0 { PieceType::Queen } else { PieceType::Null };
-                moves.push(Move {from_square: from_square.rel(b.turn), to_square: to_square.rel(b.turn), promote_type: promote_type});
-                bb = bb & !move_bb;
-            }
-        }
-    }
-
-    // knights
-    for &kh in types::KNIGHT_HOPS.iter() {
-        // captures
-        {
-            let moves_bitboard = bitboard::knight_hop(pieces_rel.knights, kh) & enemy_bitboard_rel;
-            let mut bb = moves_bitboard;
-            while bb != 0 {
-                let idx = bb.trailing_zeros();
-                let move_bb = bitboard::bitboard_from_index(idx);
-                let to_square = Square::from_index(idx).unwrap();
-                let from_square = to_square.knight_hop(kh.reverse()).unwrap();
-                moves.push(Move {from_square: from_square.rel(b.turn), to_square: to_square.rel(b.turn), promote_type: PieceType::Null});
-                bb = bb & !move_bb;
-            }
-        }
-        // non-capture knight moves
-        {
-            let moves_bitboard = bitboard::knight_hop(pieces_rel.knights, kh) & !all_bitboard_rel;
-            let mut bb = moves_bitboard;
-            while bb != 0 {
-                let idx = bb.trailing_zeros();
-                let move_bb = bitboard::bitboard_from_index(idx);
-                let to_square = Square::from_index(idx).unwrap();
-                let from_square = to_square.knight_hop(kh.reverse()).unwrap();
-                
-                moves.push(Move {from_square: from_square.rel(b.turn), to_square: to_square.rel(b.turn), promote_type: PieceType::Null});
-                bb = bb & !move_bb;
-            }
-        }
-    }
-
-    // diagonal movers (queens and bishops)
-    for &dir in types::BISHOP_DIRECTIONS.iter() {
-        let mut bitboard = pieces_rel.bishops | pieces_rel.queens;
-        for dist in 1..8 {
-            bitboard = bitboard::slide(bitboard, dir, 1);
-
-            // captures
-            let cap_moves = bitboard & enemy_bitboard_rel;
-            let mut bb = cap_moves;
-            while bb != 0 {
-                let idx = bb.trailing_zeros();
-                let move_bb = bitboard::bitboard_from_index(idx);
-                let to_square = Square::from_index(idx).unwrap();
-                let from_square = to_square.slide(dir.reverse(), dist).unwrap();
-                capscasts.push(Move {from_square: from_square.rel(b.turn), to_square: to_square.rel(b.turn), promote_type: PieceType::Null});
-                bb = bb & !move_bb;
+                push_pawn_capture(&mut moves, from_square.rel(b.turn), to_square.rel(b.turn), move_bb, enemy_bitboard_rel);
+                bb &= !move_bb;
             }
-
-            // non-capturing moves
-            let open_moves = bitboard & !all_bitboard_rel;
-            let mut bb = open_moves;
-            while bb != 0 {
-                let idx = bb.trailing_zeros();
-                let move_bb = bitboard::bitboard_from_index(idx);
-                let to_square = Square::from_index(idx).unwrap();
-                let from_square = to_square.slide(dir.reverse(), dist).unwrap();
-                moves.push(Move {from_square: from_square.rel(b.turn), to_square: to_square.rel(b.turn), promote_type: PieceType::Null});
-                bb = bb & !move_bb;
-            }
-            if open_moves == 0 {
-                break;
-            }
-
-            bitboard = open_moves; // update to use open_moves here since a piece can't slide past a square where it captures
         }
     }
 
-    // rank and file movers (queens and rooks)
-    for &dir in types::ROOK_DIRECTIONS.iter() {
-        let mut bitboard = pieces_rel.rooks | pieces_rel.queens;
-        for dist in 1..8 {
-            bitboard = bitboard::slide(bitboard, dir, 1);
-
-            // captures
-            let cap_moves = bitboard & enemy_bitboard_rel;
-            let mut bb = cap_moves;
-            while bb != 0 {
-                let idx = bb.trailing_zeros();
-                let move_bb = bitboard::bitboard_from_index(idx);
-                let to_square = Square::from_index(idx).unwrap();
-                let from_square = to_square.slide(dir.reverse(), dist).unwrap();
-                capscasts.push(Move {from_square: from_square.rel(b.turn), to_square: to_square.rel(b.turn), promote_type: PieceType::Null});
-                bb = bb & !move_bb;
-            }
-
-            // non-capturing moves
-            let open_moves = bitboard & !all_bitboard_rel;
-            let mut bb = open_moves;
-            while bb != 0 {
-                let idx = bb.trailing_zeros();
-                let move_bb = bitboard::bitboard_from_index(idx);
-                let to_square = Square::from_index(idx).unwrap();
-                let from_square = to_square.slide(dir.reverse(), dist).unwrap();
-                moves.push(Move {from_square: from_square.rel(b.turn), to_square: to_square.rel(b.turn), promote_type: PieceType::Null});
-                bb = bb & !move_bb;
-            }
-            if open_moves == 0 {
-                break;
-            }
+    // knights, sliders and the king, straight from the attack tables
+    // flipping the board maps each of their attack sets onto the flipped one, so the tables work on relative bitboards as well
+    push_piece_moves(&mut moves, pieces_rel.knights, friendly_bitboard_rel, enemy_bitboard_rel, b.turn, attacks::knight_attacks);
+    push_piece_moves(&mut moves, pieces_rel.bishops, friendly_bitboard_rel, enemy_bitboard_rel, b.turn, |sq| attacks::bishop_attacks(sq, all_bitboard_rel));
+    push_piece_moves(&mut moves, pieces_rel.rooks, friendly_bitboard_rel, enemy_bitboard_rel, b.turn, |sq| attacks::rook_attacks(sq, all_bitboard_rel));
+    push_piece_moves(&mut moves, pieces_rel.queens, friendly_bitboard_rel, enemy_bitboard_rel, b.turn, |sq| attacks::queen_attacks(sq, all_bitboard_rel));
+    push_piece_moves(&mut moves, pieces_rel.king, friendly_bitboard_rel, enemy_bitboard_rel, b.turn, attacks::king_attacks);
 
-            bitboard = open_moves; // update to use open_moves here since a piece can't slide past a square where it captures
+    let can_castle_long = if b.turn == Color::White { b.castling_rights.white_long } else { b.castling_rights.black_long };
+    let can_castle_short = if b.turn == Color::White { b.castling_rights.white_short } else { b.castling_rights.black_short };
+    // we can't castle out of check or through an attacked square
+    // like with every other pmove, whether the king ends up in check is left for the caller to test
+    let enemy_color = b.turn.opposite();
+    let castles = [
+        (can_castle_long, bitboard::LONG_CASTLE_BITBOARD, Square::D1, Square::C1, PackedMove::QUEEN_CASTLE),
+        (can_castle_short, bitboard::SHORT_CASTLE_BITBOARD, Square::F1, Square::G1, PackedMove::KING_CASTLE)
+    ];
+    for &(can_castle, path, through_sq, to_sq, flags) in castles.iter() {
+        if can_castle && (all_bitboard_rel & path) == 0 && !is_in_check(b)
+            && !is_square_attacked(b, through_sq.rel(b.turn).to_index(), enemy_color) {
+            moves.push(PackedMove::new(Square::E1.rel(b.turn), to_sq.rel(b.turn), flags));
         }
     }
 
-    // king
-    for &dir in types::KING_DIRECTIONS.iter() {
-        let mut bitboard = pieces_rel.king;
-        bitboard = bitboard::slide(bitboard, dir, 1);
-
-        // captures
-        let cap_moves = bitboard & enemy_bitboard_rel;
-        let mut bb = cap_moves;
-        while bb != 0 {
-            let idx = bb.trailing_zeros();
-            let move_bb = bitboard::bitboard_from_index(idx);
-            let to_square = Square::from_index(idx).unwrap();
-            let from_square = to_square.slide(dir.reverse(), 1).unwrap();
-            capscasts.push(Move {from_square: from_square.rel(b.turn), to_square: to_square.rel(b.turn), promote_type: PieceType::Null});
-            bb = bb & !move_bb;
-        }
+    return moves;
+}
 
-        // non-capturing moves
-        let open_moves = bitboard & !all_bitboard_rel;
-        let mut bb = open_moves;
+// pushes the moves of every piece on a square of pieces_rel that don't take one of our own pieces,
+// given the squares a piece attacks from each square
+fn push_piece_moves(moves: &mut MoveList, pieces_rel: Bitboard, friendly_bitboard_rel: Bitboard, enemy_bitboard_rel: Bitboard, turn: Color,
+    piece_attacks: impl Fn(usize) -> Bitboard) {
+    let mut pieces_bb = pieces_rel;
+    while pieces_bb != 0 {
+        let from_idx = pieces_bb.trailing_zeros();
+        let from_square = Square::from_index(from_idx).unwrap().rel(turn);
+        let mut bb = piece_attacks(from_idx as usize) & !friendly_bitboard_rel;
         while bb != 0 {
-            let idx = bb.trailing_zeros();
-            let move_bb = bitboard::bitboard_from_index(idx);
-            let to_square = Square::from_index(idx).unwrap();
-            let from_square = to_square.slide(dir.reverse(), 1).unwrap();
-            moves.push(Move {from_square: from_square.rel(b.turn), to_square: to_square.rel(b.turn), promote_type: PieceType::Null});
-            bb = bb & !move_bb;
-        }
-    }
-    let can_castle_long = if b.turn == Color::White { b.castling_rights.white_long } else { b.castling_rights.black_long };
-    let can_castle_short = if b.turn == Color::White { b.castling_rights.white_short } else { b.castling_rights.black_short };
-    // if we still have long castling rights and no pieces are in the way, check further
-    if can_castle_long && ((all_bitboard_rel & bitboard::LONG_CASTLE_BITBOARD) == 0) {
-        let null_board = utils::apply_null_move(b);
-        if test_pmove_valid(&null_board) { // we can't castle if we are in check
-            let from_sq = Square::E1.rel(b.turn);
-            let to_sq1 = Square::D1.rel(b.turn);
-            let test_move = Move {from_square: from_sq, to_square: to_sq1, promote_type: PieceType::Null};
-
-            // we also can't castle through check so we need to check the square we are castling through
-            // we don't test the square where the king will end up because it will be checked later by calc_moves
-            let test_board = utils::apply_move(b, test_move);
-            if test_pmove_valid(&test_board) {
-                let to_sq2 = Square::C1.rel(b.turn);
-                let castle_move = Move {from_square: from_sq, to_square: to_sq2, promote_type: PieceType::Null};
-                capscasts.push(castle_move);
-            }
+            let to_idx = bb.trailing_zeros();
+            let flags = if ((1u64 << to_idx) & enemy_bitboard_rel) != 0 { PackedMove::CAPTURE } else { PackedMove::QUIET };
+            moves.push(PackedMove::new(from_square, Square::from_index(to_idx).unwrap().rel(turn), flags));
+            bb &= bb - 1;
         }
+        pieces_bb &= pieces_bb - 1;
     }
-    // if we still have short castling rights and no pieces are in the way, check further
-    if can_castle_short && ((all_bitboard_rel & bitboard::SHORT_CASTLE_BITBOARD) == 0) {
-        let null_board = utils::apply_null_move(b);
-        if test_pmove_valid(&null_board) { // we can't castle if we are in check
-            let from_sq = Square::E1.rel(b.turn);
-            let to_sq1 = Square::F1.rel(b.turn);
-            let test_move = Move {from_square: from_sq, to_square: to_sq1, promote_type: PieceType::Null};
+}
 
-            // we also can't castle through check so we need to check the square we are castling through
-            // we don't test the square where the king will end up because it will be checked later by calc_moves
-            let test_board = utils::apply_move(b, test_move);
-            if test_pmove_valid(&test_board) {
-                let to_sq2 = Square::G1.rel(b.turn);
-                let castle_move = Move {from_square: from_sq, to_square: to_sq2, promote_type: PieceType::Null};
-                capscasts.push(castle_move);
-            }
+// pushes a pawn move, or one move for each piece type it can promote to if it reaches the last rank
+fn push_pawn_move(moves: &mut MoveList, from_square: Square, to_square: Square, promotes: bool, capture: bool) {
+    if promotes {
+        for &pt in types::PROMOTION_TYPES.iter() {
+            moves.push(PackedMove::new(from_square, to_square, PackedMove::promotion_flags(pt, capture)));
         }
+    } else {
+        moves.push(PackedMove::new(from_square, to_square, if capture { PackedMove::CAPTURE } else { PackedMove::QUIET }));
     }
-
-    capscasts.extend(moves);
-    return capscasts;
 }
 
-// returns false if a pmove attacks a king, true otherwise
-// use this function to check if a board is valid (useful for testing pmove validity)
-pub fn test_pmove_valid(b: &Board) -> bool {
-    let pieces = if b.turn == Color::White { b.white_bitboard_pieces } else { b.black_bitboard_pieces };
-    let enemy_pieces = if b.turn == Color::White { b.black_bitboard_pieces } else { b.white_bitboard_pieces };
-    let king = enemy_pieces.king;
-    let all_except_king = pieces.king | pieces.queens | pieces.rooks | pieces.bishops | pieces.knights | pieces.pawns
-        | enemy_pieces.queens | enemy_pieces.rooks | enemy_pieces.bishops | enemy_pieces.knights | enemy_pieces.pawns;
-    let all_except_king_mask = !all_except_king;
-    let king_idx_lo = king.trailing_zeros();
-    let king_idx_hi = king.leading_zeros();
-
-    // knight hops
-    {
-        let mut bitboard = 0u64;
-        for &kh in types::KNIGHT_HOPS.iter() {
-            bitboard |= bitboard::knight_hop(pieces.knights, kh);
-        }
-        if (bitboard & king) != 0 {
-            return false;
-        }
+// pushes a pawn capture onto the relative bitboard move_bb; if no enemy piece is there, it's onto one of the synthetic
+// enpassant pawns and captures enpassant instead
+fn push_pawn_capture(moves: &mut MoveList, from_square: Square, to_square: Square, move_bb: Bitboard, enemy_bitboard_rel: Bitboard) {
+    if (move_bb & enemy_bitboard_rel) == 0 {
+        moves.push(PackedMove::new(from_square, to_square, PackedMove::ENPASSANT_CAPTURE));
+    } else {
+        push_pawn_move(moves, from_square, to_square, (move_bb & bitboard::RANK_8) != 0, true);
     }
+}
 
-    // diagonals
-    {
-        let mut bitboard = 0u64;
-        for &dir in types::BISHOP_DIRECTIONS.iter() {
-            let mut bishops_and_queens = pieces.bishops | pieces.queens;
-            for dist in 1..8 {
-                bishops_and_queens = bitboard::slide1(bishops_and_queens, dir) & all_except_king_mask;
-                bitboard |= bishops_and_queens;
-            }
-        }
-        if (bitboard & king) != 0 {
-            return false;
-        }
-    }
+// the pieces of color c that attack the square sq
+pub fn attackers_to(b: &Board, sq: usize, c: Color) -> Bitboard {
+    return attackers_with_blockers(b.get_pieces(c), c, sq, b.white_bitboard_pieces.all | b.black_bitboard_pieces.all);
+}
 
-    // ranks and files
-    {
-        let mut bitboard = 0u64;
-        for &dir in types::ROOK_DIRECTIONS.iter() {
-            let mut rooks_and_queens = pieces.rooks | pieces.queens;
-            for dist in 1..8 {
-                rooks_and_queens = bitboard::slide1(rooks_and_queens, dir) & all_except_king_mask;
-                bitboard |= rooks_and_queens;
-            }
-        }
-        if (bitboard & king) != 0 {
-            return false;
-        }
-    }
+pub fn is_square_attacked(b: &Board, sq: usize, c: Color) -> bool {
+    return attackers_to(b, sq, c) != 0;
+}
 
-    // pawns
-    {
-        let mut bitboard = 0u64;
-        if b.turn == Color::White {
-            bitboard = bitboard::slide1(pieces.pawns, Direction::NW) | bitboard::slide1(pieces.pawns, Direction::NE);
-        } else {
-            bitboard = bitboard::slide1(pieces.pawns, Direction::SW) | bitboard::slide1(pieces.pawns, Direction::SE);
-        }
-        if (bitboard & king) != 0 {
-            return false;
-        }
-    }
+// the enemy pieces giving check to the side to move
+pub fn checkers(b: &Board) -> Bitboard {
+    let king_idx = b.get_pieces(b.turn).king.trailing_zeros() as usize;
+    return attackers_to(b, king_idx, b.turn.opposite());
+}
 
-    // other king
-    {
-        let mut friendly_king = pieces.king;
-        let mut bitboard = 0u64;
-        for &dir in types::KING_DIRECTIONS.iter() {
-            bitboard |= bitboard::slide1(friendly_king, dir);
-        }
-        if (bitboard & king) != 0 {
-            return false;
-        }
-    }
+// returns true if the side to move is in check
+pub fn is_in_check(b: &Board) -> bool {
+    return checkers(b) != 0;
+}
 
-    return true;
-}
\ No newline at end of file
+// the pieces (of color c) that attack the square sq, with only the pieces on occupied blocking sliders
+// calc_moves passes its own occupied bitboard to see the attacks on the board as it will be after a move
+fn attackers_with_blockers(pieces: &bitboard::Pieces, c: Color, sq: usize, occupied: Bitboard) -> Bitboard {
+    // a piece attacks sq exactly when the same kind of piece on sq would attack it
+    return (attacks::knight_attacks(sq) & pieces.knights)
+        | (attacks::king_attacks(sq) & pieces.king)
+        | (attacks::pawn_attacks(c.opposite(), sq) & pieces.pawns)
+        | (attacks::bishop_attacks(sq, occupied) & (pieces.bishops | pieces.queens))
+        | (attacks::rook_attacks(sq, occupied) & (pieces.rooks | pieces.queens));
+}
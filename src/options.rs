@@ -0,0 +1,136 @@
+use crate::search;
+use crate::tt;
+
+// names of the options we advertise, as sent by the gui in setoption
+pub const HASH: &str = "Hash";
+pub const CLEAR_HASH: &str = "Clear Hash";
+pub const THREADS: &str = "Threads";
+pub const PONDER: &str = "Ponder";
+pub const MULTIPV: &str = "MultiPV";
+pub const MOVE_OVERHEAD: &str = "Move Overhead";
+pub const CONTEMPT: &str = "Contempt";
+pub const ANALYSIS_CONTEMPT: &str = "Analysis Contempt";
+pub const SKILL_LEVEL: &str = "Skill Level";
+pub const DEBUG_LOG_FILE: &str = "Debug Log File";
+pub const NULL_MOVE_PRUNING: &str = "NullMovePruning";
+pub const NULL_MOVE_VERIFICATION: &str = "NullMoveVerification";
+pub const LATE_MOVE_REDUCTIONS: &str = "LateMoveReductions";
+pub const FUTILITY_PRUNING: &str = "FutilityPruning";
+
+#[derive(Debug, PartialEq, Copy, Clone)]
+pub enum OptionType {
+    Spin { default: i64, min: i64, max: i64 },
+    Check { default: bool },
+    Combo { default: &'static str, vars: &'static [&'static str] },
+    String { default: &'static str },
+    Button
+}
+
+#[derive(Debug, PartialEq, Copy, Clone)]
+pub struct UciOption {
+    pub name: &'static str,
+    pub option_type: OptionType
+}
+
+#[derive(Debug, PartialEq, Clone)]
+pub enum OptionValue {
+    Spin(i64),
+    Check(bool),
+    Combo(&'static str),
+    String(String),
+    Button
+}
+
+#[derive(Debug, PartialEq, Clone)]
+pub enum OptionError {
+    MissingName,
+    UnknownOption(String),
+    InvalidValue(&'static str, String)
+}
+
+// every option the engine supports, in the order they're listed in the uci response
+pub const OPTIONS: [UciOption; 14] = [
+    UciOption { name: HASH, option_type: OptionType::Spin { default: tt::DEFAULT_HASH_MB as i64, min: 1, max: tt::MAX_HASH_MB as i64 } },
+    UciOption { name: CLEAR_HASH, option_type: OptionType::Button },
+    UciOption { name: THREADS, option_type: OptionType::Spin { default: 1, min: 1, max: search::MAX_THREADS as i64 } },
+    // we ponder whenever the gui sends go ponder, but guis only do that for engines that have this option
+    UciOption { name: PONDER, option_type: OptionType::Check { default: false } },
+    UciOption { name: MULTIPV, option_type: OptionType::Spin { default: 1, min: 1, max: search::MAX_MULTIPV as i64 } },
+    UciOption { name: MOVE_OVERHEAD, option_type: OptionType::Spin {
+        default: search::DEFAULT_MOVE_OVERHEAD_MS as i64, min: 0, max: search::MAX_MOVE_OVERHEAD_MS as i64 } },
+    UciOption { name: CONTEMPT, option_type: OptionType::Spin { default: 0, min: -search::MAX_CONTEMPT as i64, max: search::MAX_CONTEMPT as i64 } },
+    UciOption { name: ANALYSIS_CONTEMPT, option_type: OptionType::Combo { default: "Off", vars: &["Off", "White", "Black", "Both"] } },
+    UciOption { name: SKILL_LEVEL, option_type: OptionType::Spin { default: search::MAX_SKILL_LEVEL as i64, min: 0, max: search::MAX_SKILL_LEVEL as i64 } },
+    UciOption { name: DEBUG_LOG_FILE, option_type: OptionType::String { default: "" } },
+    UciOption { name: NULL_MOVE_PRUNING, option_type: OptionType::Check { default: true } },
+    UciOption { name: NULL_MOVE_VERIFICATION, option_type: OptionType::Check { default: true } },
+    UciOption { name: LATE_MOVE_REDUCTIONS, option_type: OptionType::Check { default: true } },
+    UciOption { name: FUTILITY_PRUNING, option_type: OptionType::Check { default: true } }
+];
+
+impl UciOption {
+    // the line advertising this option in the uci response
+    pub fn to_uci(self) -> String {
+        let details = match self.option_type {
+            OptionType::Spin { default, min, max } => format!("type spin default {} min {} max {}", default, min, max),
+            OptionType::Check { default } => format!("type check default {}", default),
+            OptionType::Combo { default, vars } => {
+                let vars: Vec<String> = vars.iter().map(|v| format!("var {}", v)).collect();
+                format!("type combo default {} {}", default, vars.join(" "))
+            },
+            // uci uses <empty> for an empty string
+            OptionType::String { default } => format!("type string default {}", if default.is_empty() { "<empty>" } else { default }),
+            OptionType::Button => String::from("type button")
+        };
+        return format!("option name {} {}", self.name, details);
+    }
+
+    pub fn parse_value(&self, value: &str) -> Result<OptionValue, OptionError> {
+        let invalid = || OptionError::InvalidValue(self.name, value.to_string());
+        return match self.option_type {
+            OptionType::Spin { min, max, .. } => match value.parse::<i64>() {
+                Ok(v) if (min..=max).contains(&v) => Ok(OptionValue::Spin(v)),
+                _ => Err(invalid())
+            },
+            OptionType::Check { .. } => value.parse::<bool>().map(OptionValue::Check).map_err(|_| invalid()),
+            OptionType::Combo { vars, .. } => match vars.iter().find(|v| v.eq_ignore_ascii_case(value)) {
+                Some(v) => Ok(OptionValue::Combo(v)),
+                None => Err(invalid())
+            },
+            OptionType::String { .. } => Ok(OptionValue::String(if value == "<empty>" { String::new() } else { value.to_string() })),
+            OptionType::Button => Ok(OptionValue::Button)
+        };
+    }
+}
+
+// option names are case insensitive
+pub fn find_option(name: &str) -> Option<&'static UciOption> {
+    return OPTIONS.iter().find(|o| o.name.eq_ignore_ascii_case(name));
+}
+
+// parses "setoption name <id> [value <x>]", where both the name and the value may contain spaces
+pub fn parse_setoption(line: &str) -> Result<(&'static UciOption, OptionValue), OptionError> {
+    let tokens: Vec<&str> = line.split_whitespace().collect();
+    if tokens.get(1) != Some(&"name") {
+        return Err(OptionError::MissingName);
+    }
+    let value_idx = tokens.iter().position(|&t| t == "value").unwrap_or(tokens.len());
+    let name = tokens[2.min(value_idx)..value_idx].join(" ");
+    let value = tokens[(value_idx+1).min(tokens.len())..].join(" ");
+    if name.is_empty() {
+        return Err(OptionError::MissingName);
+    }
+
+    let option = find_option(&name).ok_or(OptionError::UnknownOption(name))?;
+    return Ok((option, option.parse_value(&value)?));
+}
+
+impl std::fmt::Display for OptionError {
+    fn fmt(&self, f: &mut std::fmt::Formatter) -> std::fmt::Result {
+        return match self {
+            OptionError::MissingName => write!(f, "missing option name"),
+            OptionError::UnknownOption(name) => write!(f, "unknown option: {}", name),
+            OptionError::InvalidValue(name, value) => write!(f, "invalid {} value: {}", name, value)
+        };
+    }
+}
@@ -0,0 +1,145 @@
+use crate::types::{Board, PackedMove};
+use crate::utils;
+use crate::move_search;
+
+// counts the leaf nodes of the legal move tree to the given depth
+pub fn perft(b: &Board, depth: u32) -> u64 {
+    if depth == 0 {
+        return 1;
+    }
+
+    let moves = move_search::calc_moves(b);
+    if depth == 1 {
+        return moves.len() as u64;
+    }
+
+    let mut nodes = 0u64;
+    for m in moves.iter() {
+        nodes += perft(&utils::apply_move(b, m), depth-1);
+    }
+    return nodes;
+}
+
+// like perft, but reports the node count below each root move separately
+// comparing these counts against another engine narrows a perft mismatch down to a single line
+pub fn divide(b: &Board, depth: u32) -> Vec<(PackedMove, u64)> {
+    let mut counts = Vec::<(PackedMove, u64)>::new();
+    if depth == 0 {
+        return counts;
+    }
+
+    for m in move_search::calc_moves(b).iter() {
+        counts.push((m, perft(&utils::apply_move(b, m), depth-1)));
+    }
+    counts.sort_by_key(|(m, _)| m.to_uci());
+    return counts;
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    // reference positions and node counts from https://www.chessprogramming.org/Perft_Results
+    const KIWIPETE: &str = "r3k2r/p1ppqpb1/bn2pnp1/3PN3/1p2P3/2N2Q1p/PPPBBPPP/R3K2R w KQkq - 0 1";
+    const POSITION_3: &str = "8/2p5/3p4/KP5r/1R3p1k/8/4P1P1/8 w - - 0 1";
+    const POSITION_4: &str = "r3k2r/Pppp1ppp/1b3nbN/nP6/BBP1P3/q4N2/Pp1P2PP/R2Q1RK1 w kq - 0 1";
+    const POSITION_4_MIRRORED: &str = "r2q1rk1/pP1p2pp/Q4n2/bbp1p3/Np6/1B3NBn/pPPP1PPP/R3K2R b KQ - 0 1";
+    const POSITION_5: &str = "rnbq1k1r/pp1Pbppp/2p5/8/2B5/8/PPP1NnPP/RNBQK2R w KQ - 1 8";
+    const POSITION_6: &str = "r4rk1/1pp1qppp/p1np1n2/2b1p1B1/2B1P1b1/P1NP1N2/1PP1QPPP/R4RK1 w - - 0 10";
+
+    // legal move generation edge cases
+    const ENPASSANT_HORIZONTAL_PIN: &str = "8/8/8/KPp4r/8/8/8/4k3 w - c6 0 2";
+    const ENPASSANT_CAPTURES_CHECKER: &str = "8/8/8/2k5/3Pp3/8/8/4K3 b - d3 0 1";
+    const DOUBLE_CHECK: &str = "4k3/8/8/8/8/5n2/8/r3K2R w K - 0 1";
+    const PINNED_SLIDERS: &str = "4k3/4r3/8/1b6/8/3B4/4R3/4K3 w - - 0 1";
+
+    // the old way of generating legal moves: make every pmove and keep the ones that don't leave our king attacked
+    fn calc_moves_filtered(b: &Board) -> Vec<PackedMove> {
+        return move_search::calc_pmoves(b).iter().filter(|&m| {
+            let next_b = utils::apply_move(b, m);
+            let king_idx = next_b.get_pieces(b.turn).king.trailing_zeros() as usize;
+            !move_search::is_square_attacked(&next_b, king_idx, next_b.turn)
+        }).collect();
+    }
+
+    // checks that calc_moves gives the same moves as calc_moves_filtered in every position of the tree, and returns its perft
+    // the moves are compared with their flags, which also have to match the flags PackedMove::from_uci works out
+    fn compare_generators(b: &Board, depth: u32) -> u64 {
+        for m in move_search::calc_moves(b).iter() {
+            assert_eq!(PackedMove::from_uci(b, &m.to_uci()), Some(m), "flags of {} in {}", m.to_uci(), b.to_fen());
+        }
+        let mut moves: Vec<PackedMove> = move_search::calc_moves(b).iter().collect();
+        let mut expected = calc_moves_filtered(b);
+        moves.sort();
+        expected.sort();
+        assert_eq!(moves, expected, "legal moves of {}", b.to_fen());
+        if depth <= 1 {
+            return moves.len() as u64;
+        }
+        return move_search::calc_moves(b).iter().map(|m| compare_generators(&utils::apply_move(b, m), depth-1)).sum();
+    }
+
+    fn check_perft(fen: &str, expected: &[u64]) {
+        let b = Board::from_fen(fen).unwrap();
+        for (i, &nodes) in expected.iter().enumerate() {
+            let depth = (i + 1) as u32;
+            assert_eq!(perft(&b, depth), nodes, "perft({}) of {}", depth, fen);
+        }
+    }
+
+    #[test]
+    fn perft_startpos() {
+        let b = utils::START_POSITION;
+        assert_eq!(perft(&b, 1), 20);
+        assert_eq!(perft(&b, 2), 400);
+        assert_eq!(perft(&b, 3), 8_902);
+        assert_eq!(perft(&b, 4), 197_281);
+    }
+
+    #[test]
+    fn perft_kiwipete() {
+        check_perft(KIWIPETE, &[48, 2_039, 97_862]);
+    }
+
+    #[test]
+    fn perft_position_3() {
+        // enpassant captures, including ones that would leave the king in check along the rank
+        check_perft(POSITION_3, &[14, 191, 2_812, 43_238]);
+    }
+
+    #[test]
+    fn perft_position_4() {
+        // promotions, underpromotions and castling out of and through check
+        check_perft(POSITION_4, &[6, 264, 9_467]);
+        check_perft(POSITION_4_MIRRORED, &[6, 264, 9_467]);
+    }
+
+    #[test]
+    fn perft_position_5() {
+        check_perft(POSITION_5, &[44, 1_486, 62_379]);
+    }
+
+    #[test]
+    fn perft_position_6() {
+        check_perft(POSITION_6, &[46, 2_079, 89_890]);
+    }
+
+    #[test]
+    fn legal_moves_match_filtered_pmoves() {
+        let positions = [(KIWIPETE, 3), (POSITION_3, 4), (POSITION_4, 3), (POSITION_4_MIRRORED, 3), (POSITION_5, 3),
+            (ENPASSANT_HORIZONTAL_PIN, 3), (ENPASSANT_CAPTURES_CHECKER, 3), (DOUBLE_CHECK, 3), (PINNED_SLIDERS, 3)];
+        for &(fen, depth) in positions.iter() {
+            let b = Board::from_fen(fen).unwrap();
+            assert_eq!(compare_generators(&b, depth), perft(&b, depth), "perft({}) of {}", depth, fen);
+        }
+        assert_eq!(compare_generators(&utils::START_POSITION, 4), 197_281);
+    }
+
+    #[test]
+    fn divide_sums_to_perft() {
+        let b = Board::from_fen(KIWIPETE).unwrap();
+        let counts = divide(&b, 2);
+        assert_eq!(counts.len(), 48);
+        assert_eq!(counts.iter().map(|(_, n)| n).sum::<u64>(), perft(&b, 2));
+    }
+}
@@ -0,0 +1,540 @@
+use std::time::{Duration, Instant};
+use std::sync::Arc;
+use std::sync::atomic::{AtomicBool, AtomicU64, Ordering};
+use std::thread;
+
+use rand::Rng;
+use rand::seq::SliceRandom;
+
+use crate::types::{Color, Board, Move, PackedMove, History};
+use crate::eval;
+use crate::move_search;
+use crate::tt::TranspositionTable;
+use crate::move_order::{KillerMoves, HistoryScores};
+use crate::move_order;
+
+// depth we search to when go is given no limits at all
+pub const DEFAULT_DEPTH: i32 = 6;
+pub const MAX_DEPTH: i32 = 64;
+pub const MAX_MULTIPV: usize = 256;
+pub const MAX_THREADS: usize = 256;
+pub const MAX_CONTEMPT: i32 = 100;
+
+// below the maximum skill level we limit the depth, search a few lines and pick one of them with some randomness
+pub const MAX_SKILL_LEVEL: i32 = 20;
+const SKILL_MULTIPV: usize = 4;
+const SKILL_WEAKNESS_PER_LEVEL: i32 = 20; // the most random noise added to a line's eval, per level below the maximum
+
+// time we keep in reserve per move for communication with the gui
+pub const DEFAULT_MOVE_OVERHEAD_MS: u64 = 30;
+pub const MAX_MOVE_OVERHEAD_MS: u64 = 5000;
+// number of moves we budget for when the gui doesn't send movestogo
+const DEFAULT_MOVES_TO_GO: u32 = 30;
+// how many nodes we search between checks of the clock (must be a power of two)
+const NODES_PER_TIME_CHECK: u64 = 2048;
+
+pub const BENCH_DEPTH: i32 = 6;
+const BENCH_POSITIONS: [&str; 8] = [
+    "rnbqkbnr/pppppppp/8/8/8/8/PPPPPPPP/RNBQKBNR w KQkq - 0 1",
+    "r3k2r/p1ppqpb1/bn2pnp1/3PN3/1p2P3/2N2Q1p/PPPBBPPP/R3K2R w KQkq - 0 1",
+    "8/2p5/3p4/KP5r/1R3p1k/8/4P1P1/8 w - - 0 1",
+    "r3k2r/Pppp1ppp/1b3nbN/nP6/BBP1P3/q4N2/Pp1P2PP/R2Q1RK1 w kq - 0 1",
+    "rnbq1k1r/pp1Pbppp/2p5/8/2B5/8/PPP1NnPP/RNBQK2R w KQ - 1 8",
+    "r4rk1/1pp1qppp/p1np1n2/2b1p1B1/2B1P1b1/P1NP1N2/1PP1QPPP/R4RK1 w - - 0 10",
+    "r1bqkb1r/pppp1ppp/2n2n2/4p3/2B1P3/5N2/PPPP1PPP/RNBQK2R w KQkq - 4 4",
+    "6k1/5ppp/8/8/8/8/5PPP/3R2K1 w - - 0 1"
+];
+
+// the limits sent with the uci go command
+#[derive(Debug, PartialEq, Clone, Default)]
+pub struct SearchLimits {
+    pub wtime: Option<Duration>,
+    pub btime: Option<Duration>,
+    pub winc: Option<Duration>,
+    pub binc: Option<Duration>,
+    pub movestogo: Option<u32>,
+    pub movetime: Option<Duration>,
+    pub depth: Option<i32>,
+    pub nodes: Option<u64>,
+    pub mate: Option<i32>, // stop as soon as we've found a mate in at most this many moves
+    pub searchmoves: Vec<Move>, // only search these root moves, or all of them if empty
+    pub infinite: bool, // search until told to stop
+    pub ponder: bool // search on the opponent's time; the clock only starts at ponderhit
+}
+
+// flags the uci loop uses to control a search running on another thread
+#[derive(Debug, Clone, Default)]
+pub struct SearchSignals {
+    pub stop: Arc<AtomicBool>,
+    pub ponder: Arc<AtomicBool> // cleared by ponderhit, after which the search plays by its normal limits
+}
+
+// switches for the search techniques that are optional, mostly so their effect can be measured with bench
+#[derive(Debug, PartialEq, Copy, Clone)]
+pub struct SearchOptions {
+    pub null_move_pruning: bool,
+    pub null_move_verification: bool,
+    pub late_move_reductions: bool,
+    pub futility_pruning: bool,
+    pub multipv: usize, // how many of the best root moves get an exact score and pv
+    pub threads: usize,
+    pub move_overhead: Duration,
+    pub contempt: i32, // how much we dislike draws, in centipawns
+    pub analysis_contempt: AnalysisContempt,
+    pub skill_level: i32
+}
+
+// contempt is left out of infinite analysis searches, unless this asks for it for one or both sides
+#[derive(Debug, PartialEq, Copy, Clone)]
+pub enum AnalysisContempt {
+    Off,
+    White,
+    Black,
+    Both
+}
+
+#[derive(Debug, PartialEq, Copy, Clone)]
+pub struct TimeBudget {
+    pub soft: Duration, // we don't start a new iteration after spending half of this
+    pub hard: Duration // we abort the search in progress after this
+}
+
+// triangular pv table: line ply holds the best line found so far from the node at that ply
+// when a move raises alpha, its line becomes the move followed by the line of the child node
+pub struct PvTable {
+    lines: Vec<Vec<PackedMove>>
+}
+
+// late move reductions, indexed by depth and by the move's position in the ordering
+pub type ReductionTable = [[i32; 64]; 64];
+
+// state shared by every node of a single search thread
+pub struct SearchContext<'a> {
+    pub history: History,
+    pub tt: &'a TranspositionTable,
+    pub killer_moves: KillerMoves,
+    pub history_scores: HistoryScores,
+    pub options: SearchOptions,
+    pub reductions: ReductionTable,
+    pub pv: PvTable,
+    pub seldepth: i32, // the deepest ply reached in this iteration, including quiescence search
+    pub contempt: i32, // from the point of view of the side to move at the root
+    pub nodes: u64,
+    pub stopped: bool,
+    pub can_stop: bool, // false while searching the first iteration so we always have a move to play
+    pub pondering: bool,
+    node_limit: Option<u64>,
+    node_counter: Arc<AtomicU64>, // nodes searched by all threads, updated every NODES_PER_TIME_CHECK nodes
+    signals: SearchSignals,
+    start: Instant,
+    clock_start: Instant, // when our own clock started running, which is later than start when pondering
+    hard_limit: Option<Duration>
+}
+
+#[derive(Debug, PartialEq, Clone)]
+pub struct SearchResult {
+    pub best_move: Option<PackedMove>,
+    pub pv: Vec<PackedMove>,
+    pub eval: i32,
+    pub depth: i32,
+    pub nodes: u64
+}
+
+impl SearchLimits {
+    pub fn from_uci<'a>(tokens: impl Iterator<Item = &'a str>) -> SearchLimits {
+        let mut limits = SearchLimits::default();
+        let mut tokens = tokens.peekable();
+        while let Some(token) = tokens.next() {
+            match token {
+                "infinite" => limits.infinite = true,
+                "ponder" => limits.ponder = true,
+                "searchmoves" => {
+                    while let Some(m) = tokens.peek().and_then(|t| Move::from_uci(t)) {
+                        limits.searchmoves.push(m);
+                        tokens.next();
+                    }
+                },
+                _ => { }
+            }
+            let value = match tokens.peek().map(|v| v.parse::<u64>()) {
+                Some(Ok(v)) => v,
+                _ => continue // unknown or valueless token
+            };
+            match token {
+                "wtime" => limits.wtime = Some(Duration::from_millis(value)),
+                "btime" => limits.btime = Some(Duration::from_millis(value)),
+                "winc" => limits.winc = Some(Duration::from_millis(value)),
+                "binc" => limits.binc = Some(Duration::from_millis(value)),
+                "movestogo" => limits.movestogo = Some(value as u32),
+                "movetime" => limits.movetime = Some(Duration::from_millis(value)),
+                "depth" => limits.depth = Some(value.min(MAX_DEPTH as u64) as i32),
+                "nodes" => limits.nodes = Some(value),
+                "mate" => limits.mate = Some(value.min(MAX_DEPTH as u64) as i32),
+                _ => continue
+            }
+            tokens.next();
+        }
+        return limits;
+    }
+
+    // works out how long we may think about this move, or None if the search isn't timed
+    pub fn time_budget(&self, c: Color, move_overhead: Duration) -> Option<TimeBudget> {
+        if let Some(movetime) = self.movetime {
+            let t = movetime.checked_sub(move_overhead).unwrap_or(Duration::from_millis(1));
+            return Some(TimeBudget { soft: t, hard: t });
+        }
+
+        let time = if c == Color::White { self.wtime } else { self.btime }?;
+        let inc = (if c == Color::White { self.winc } else { self.binc }).unwrap_or_default();
+        let moves_to_go = self.movestogo.unwrap_or(DEFAULT_MOVES_TO_GO).clamp(1, 50);
+
+        // spread the remaining time over the remaining moves, and spend most of the increment right away
+        // never plan to use more than half of the clock on a single move
+        let remaining = time.checked_sub(move_overhead).unwrap_or(Duration::from_millis(1));
+        let hard = (remaining / 2).max(Duration::from_millis(1));
+        let soft = (remaining / moves_to_go + inc * 3 / 4).min(hard);
+        return Some(TimeBudget { soft, hard: hard.min(soft * 3) });
+    }
+}
+
+impl Default for SearchOptions {
+    fn default() -> SearchOptions {
+        return SearchOptions {
+            null_move_pruning: true,
+            null_move_verification: true,
+            late_move_reductions: true,
+            futility_pruning: true,
+            multipv: 1,
+            threads: 1,
+            move_overhead: Duration::from_millis(DEFAULT_MOVE_OVERHEAD_MS),
+            contempt: 0,
+            analysis_contempt: AnalysisContempt::Off,
+            skill_level: MAX_SKILL_LEVEL
+        };
+    }
+}
+
+impl PvTable {
+    pub fn new() -> PvTable {
+        return PvTable { lines: vec![Vec::new(); (eval::MAX_PLY + 1) as usize] };
+    }
+
+    pub fn clear(&mut self, ply: i32) {
+        self.lines[ply as usize].clear();
+    }
+
+    pub fn update(&mut self, ply: i32, m: PackedMove) {
+        let (head, tail) = self.lines.split_at_mut(ply as usize + 1);
+        let line = &mut head[ply as usize];
+        line.clear();
+        line.push(m);
+        line.extend_from_slice(&tail[0]);
+    }
+
+    pub fn line(&self, ply: i32) -> &[PackedMove] {
+        return &self.lines[ply as usize];
+    }
+}
+
+pub fn new_reduction_table() -> ReductionTable {
+    let mut table = [[0; 64]; 64];
+    for (depth, row) in table.iter_mut().enumerate().skip(1) {
+        for (move_num, reduction) in row.iter_mut().enumerate().skip(1) {
+            // deeper searches and later moves are reduced more, but both only logarithmically
+            *reduction = (0.75 + (depth as f64).ln() * (move_num as f64).ln() / 2.25) as i32;
+        }
+    }
+    return table;
+}
+
+impl<'a> SearchContext<'a> {
+    pub fn new(history: History, tt: &'a TranspositionTable, options: SearchOptions, signals: &SearchSignals,
+        hard_limit: Option<Duration>, node_limit: Option<u64>, node_counter: &Arc<AtomicU64>) -> SearchContext<'a> {
+        let start = Instant::now();
+        return SearchContext {
+            history,
+            tt,
+            killer_moves: move_order::new_killer_moves(),
+            history_scores: HistoryScores::new(),
+            options,
+            reductions: new_reduction_table(),
+            pv: PvTable::new(),
+            seldepth: 0,
+            contempt: 0,
+            nodes: 0,
+            stopped: false,
+            can_stop: false,
+            pondering: signals.ponder.load(Ordering::Relaxed),
+            node_limit,
+            node_counter: Arc::clone(node_counter),
+            signals: signals.clone(),
+            start,
+            clock_start: start,
+            hard_limit
+        };
+    }
+
+    pub fn elapsed(&self) -> Duration {
+        return self.start.elapsed();
+    }
+
+    // the nodes searched by every thread, give or take the ones other threads haven't added to the counter yet
+    pub fn total_nodes(&self) -> u64 {
+        return self.node_counter.load(Ordering::Relaxed) + (self.nodes & (NODES_PER_TIME_CHECK - 1));
+    }
+
+    // the time we've spent on our own clock
+    pub fn clock_elapsed(&self) -> Duration {
+        return self.clock_start.elapsed();
+    }
+
+    // picks up stop and ponderhit from the uci thread
+    pub fn poll_signals(&mut self) {
+        if self.signals.stop.load(Ordering::Relaxed) {
+            self.stopped = true;
+        } else if self.pondering && !self.signals.ponder.load(Ordering::Relaxed) {
+            // the opponent played the move we were pondering on, so from now on we're thinking on our own time
+            self.pondering = false;
+            self.clock_start = Instant::now();
+        }
+    }
+
+    // called once per node; returns true if the search should unwind as quickly as possible
+    pub fn should_stop(&mut self) -> bool {
+        self.nodes += 1;
+        if let Some(limit) = self.node_limit {
+            // checked on every node so fixed node searches with one thread are exactly reproducible
+            if self.can_stop && self.total_nodes() >= limit {
+                self.stopped = true;
+            }
+        }
+        if (self.nodes & (NODES_PER_TIME_CHECK - 1)) == 0 {
+            self.node_counter.fetch_add(NODES_PER_TIME_CHECK, Ordering::Relaxed);
+        }
+        if !self.stopped && (self.nodes & (NODES_PER_TIME_CHECK - 1)) == 0 {
+            self.poll_signals();
+            if let Some(limit) = self.hard_limit {
+                if self.can_stop && !self.pondering && self.clock_elapsed() >= limit {
+                    self.stopped = true;
+                }
+            }
+        }
+        return self.stopped;
+    }
+}
+
+// lazy smp: every thread searches the same position, and they only share the transposition table
+// the helper threads mostly help by filling the table with results that the main thread can then use
+pub fn search(b: &Board, history: &History, limits: &SearchLimits, options: &SearchOptions, signals: &SearchSignals, tt: &TranspositionTable) -> SearchResult {
+    let budget = limits.time_budget(b.turn, options.move_overhead);
+    let root_moves = calc_root_moves(b, limits, budget.is_some());
+    let contempt = root_contempt(b.turn, limits, options);
+    let node_counter = Arc::new(AtomicU64::new(0));
+    let helper_signals = SearchSignals::default(); // helpers only stop when the main thread is done
+
+    return thread::scope(|scope| {
+        for thread_idx in 1..options.threads {
+            let mut ctx = SearchContext::new(history.clone(), tt, *options, &helper_signals, None, None, &node_counter);
+            ctx.contempt = contempt;
+            let root_moves = root_moves.clone();
+            scope.spawn(move || helper_search(b, root_moves, thread_idx, ctx));
+        }
+
+        let mut ctx = SearchContext::new(history.clone(), tt, *options, signals, budget.map(|t| t.hard), limits.nodes, &node_counter);
+        ctx.contempt = contempt;
+        let result = main_search(b, root_moves, limits, budget, ctx);
+        helper_signals.stop.store(true, Ordering::Relaxed);
+        return result;
+    });
+}
+
+// the contempt of the side to move at the root
+fn root_contempt(c: Color, limits: &SearchLimits, options: &SearchOptions) -> i32 {
+    if !limits.infinite {
+        return options.contempt;
+    }
+    return match (options.analysis_contempt, c) {
+        (AnalysisContempt::Both, _) | (AnalysisContempt::White, Color::White) | (AnalysisContempt::Black, Color::Black) => options.contempt,
+        // the other side has the contempt, so to us a draw is better than it looks
+        (AnalysisContempt::White, Color::Black) | (AnalysisContempt::Black, Color::White) => -options.contempt,
+        (AnalysisContempt::Off, _) => 0
+    };
+}
+
+// the root moves in the order the search should try them first, restricted to the searchmoves if there are any
+fn calc_root_moves(b: &Board, limits: &SearchLimits, timed: bool) -> Vec<(i32, PackedMove)> {
+    let legal_moves = move_search::calc_moves(b);
+    // searchmoves are parsed without a position, so they're compared without the flags
+    for m in limits.searchmoves.iter().filter(|&&m| !legal_moves.iter().any(|lm| lm.to_move() == m)) {
+        println!("info string ignoring illegal searchmove {}", m.to_uci());
+    }
+    let mut root_moves: Vec<(i32, PackedMove)> = legal_moves.iter()
+        .filter(|m| limits.searchmoves.is_empty() || limits.searchmoves.contains(&m.to_move()))
+        .map(|m| (0, m))
+        .collect();
+    if root_moves.is_empty() && !limits.searchmoves.is_empty() {
+        // none of the searchmoves were legal, so there's nothing to restrict the search to
+        root_moves = legal_moves.iter().map(|m| (0, m)).collect();
+    }
+    if timed {
+        // shuffle to make our move choices in games a little more interesting
+        // untimed searches stay deterministic so their node counts can be compared
+        root_moves.shuffle(&mut rand::thread_rng());
+    }
+    return root_moves;
+}
+
+// a helper thread runs iterative deepening without limits or output until the main thread stops it
+// odd helpers search one ply ahead, so the threads aren't all working on the same depth at the same time
+fn helper_search(b: &Board, mut root_moves: Vec<(i32, PackedMove)>, thread_idx: usize, mut ctx: SearchContext) {
+    ctx.history.set_root();
+    ctx.can_stop = true;
+
+    let first_depth = 1 + (thread_idx % 2) as i32;
+    for depth in first_depth..(MAX_DEPTH+1) {
+        if root_moves.is_empty() {
+            break;
+        }
+        eval::search_root(b, &mut root_moves, depth, &mut ctx);
+        if ctx.stopped {
+            break;
+        }
+    }
+}
+
+// iterative deepening: search the root moves to depth 1, 2, 3, ... until we run out of time
+// the result comes from the last iteration that completed, since an aborted iteration hasn't looked at every move
+// infinite and ponder searches don't return before they're told to stop or ponderhit arrives, as uci requires
+fn main_search(b: &Board, mut root_moves: Vec<(i32, PackedMove)>, limits: &SearchLimits, budget: Option<TimeBudget>, mut ctx: SearchContext) -> SearchResult {
+    let max_depth = match (limits.depth, budget) {
+        (Some(depth), _) => depth.clamp(1, MAX_DEPTH),
+        (None, Some(_)) => MAX_DEPTH,
+        (None, None) if limits.infinite || limits.ponder || limits.nodes.is_some() || limits.mate.is_some() => MAX_DEPTH,
+        (None, None) => DEFAULT_DEPTH
+    };
+    let weakened = ctx.options.skill_level < MAX_SKILL_LEVEL;
+    let max_depth = if weakened { max_depth.min(1 + ctx.options.skill_level) } else { max_depth };
+    ctx.history.set_root();
+
+    let mut result = SearchResult { best_move: root_moves.first().map(|&(_, m)| m), pv: Vec::new(), eval: 0, depth: 0, nodes: 0 };
+    let mut last_lines: Vec<(i32, Vec<PackedMove>)> = Vec::new();
+
+    let multipv = if weakened { ctx.options.multipv.max(SKILL_MULTIPV) } else { ctx.options.multipv };
+    let multipv = multipv.clamp(1, root_moves.len().max(1));
+
+    for depth in 1..(max_depth+1) {
+        if root_moves.is_empty() {
+            break; // checkmate or stalemate
+        }
+
+        // alpha-beta only gives the best move an exact score, so for multipv we search the root once per line,
+        // each time leaving out the moves of the lines we already have
+        ctx.seldepth = 0;
+        let mut lines: Vec<(i32, Vec<PackedMove>)> = Vec::new();
+        for pv_idx in 0..multipv {
+            eval::search_root(b, &mut root_moves[pv_idx..], depth, &mut ctx);
+            if ctx.stopped {
+                break;
+            }
+            lines.push((root_moves[pv_idx].0, ctx.pv.line(0).to_vec()));
+        }
+        if ctx.stopped {
+            break;
+        }
+
+        // a later line can come out ahead of an earlier one when the search is unstable
+        lines.sort_by_key(|l| -l.0);
+        root_moves[..multipv].sort_by_key(|k| -k.0);
+        for (i, (eval, pv)) in lines.iter().enumerate() {
+            print_info(depth, i + 1, *eval, pv, &ctx);
+        }
+
+        let (eval, best_move) = root_moves[0];
+        result = SearchResult { best_move: Some(best_move), pv: lines[0].1.clone(), eval, depth, nodes: ctx.total_nodes() };
+        last_lines = lines;
+
+        ctx.can_stop = true;
+        ctx.poll_signals();
+
+        if let Some(mate) = limits.mate {
+            // a mate in n moves is 2n - 1 plies away
+            if eval >= eval::EVAL_MATE && (plies_to_mate(eval) + 1)/2 <= mate {
+                break;
+            }
+        }
+
+        // the next iteration usually takes a lot longer than all the previous ones combined
+        // go movetime asks us to use all of it, so there we keep deepening until the hard limit stops us
+        if let Some(t) = budget {
+            if !ctx.pondering && limits.movetime.is_none() && ctx.clock_elapsed() >= t.soft / 2 {
+                break;
+            }
+        }
+    }
+
+    while !ctx.stopped && (limits.infinite || ctx.pondering) {
+        thread::sleep(Duration::from_millis(1));
+        ctx.poll_signals();
+    }
+
+    if weakened && !last_lines.is_empty() {
+        let (eval, pv) = last_lines.swap_remove(pick_skill_line(&last_lines, ctx.options.skill_level));
+        result = SearchResult { best_move: pv.first().copied(), pv, eval, ..result };
+    }
+
+    return result;
+}
+
+// adds random noise to the eval of each line, more for lower skill levels, and returns the index of the best one
+fn pick_skill_line(lines: &[(i32, Vec<PackedMove>)], skill_level: i32) -> usize {
+    let max_noise = (MAX_SKILL_LEVEL - skill_level) * SKILL_WEAKNESS_PER_LEVEL;
+    let mut rng = rand::thread_rng();
+    return (0..lines.len()).max_by_key(|&i| lines[i].0 + rng.gen_range(0, max_noise + 1)).unwrap_or(0);
+}
+
+// searches a fixed set of positions to a fixed depth and reports the total node count and speed
+// the node count only changes when the search does, so this is a quick way to measure pruning and move ordering changes
+pub fn bench(depth: i32, options: &SearchOptions) {
+    let tt = TranspositionTable::new(crate::tt::DEFAULT_HASH_MB);
+    let limits = SearchLimits { depth: Some(depth), ..SearchLimits::default() };
+    let signals = SearchSignals::default();
+    let start = Instant::now();
+    let mut total_nodes = 0u64;
+
+    for fen in BENCH_POSITIONS.iter() {
+        let b = Board::from_fen(fen).unwrap();
+        tt.clear();
+        let result = search(&b, &History::new(&b), &limits, options, &signals, &tt);
+        println!("bench {} nodes {} bestmove {}", fen, result.nodes, result.best_move.map(|m| m.to_uci()).unwrap_or_default());
+        total_nodes += result.nodes;
+    }
+
+    let elapsed = start.elapsed();
+    let nps = (total_nodes as f64 / elapsed.as_secs_f64().max(0.001)) as u64;
+    println!("bench depth {} nodes {} time {} nps {}", depth, total_nodes, elapsed.as_millis(), nps);
+}
+
+// one info line per completed iteration and multipv line, with the full principal variation
+fn print_info(depth: i32, multipv: usize, eval: i32, pv: &[PackedMove], ctx: &SearchContext) {
+    let time = ctx.elapsed();
+    let nodes = ctx.total_nodes();
+    let nps = (nodes as f64 / time.as_secs_f64().max(0.001)) as u64;
+    let pv: Vec<String> = pv.iter().map(|m| m.to_uci()).collect();
+    println!("info depth {} seldepth {} multipv {} score {} nodes {} nps {} time {} hashfull {} pv {}",
+        depth, ctx.seldepth.max(depth), multipv, uci_score(eval), nodes, nps, time.as_millis(), ctx.tt.hashfull(), pv.join(" "));
+}
+
+// mate scores count plies, but uci wants the number of moves until mate, negative if we're the ones getting mated
+fn uci_score(e: i32) -> String {
+    if e >= eval::EVAL_MATE {
+        return format!("mate {}", (plies_to_mate(e) + 1)/2);
+    } else if e <= -eval::EVAL_MATE {
+        return format!("mate {}", -plies_to_mate(e)/2);
+    }
+    return format!("cp {}", e);
+}
+
+// e must be a mate score, for either side
+fn plies_to_mate(e: i32) -> i32 {
+    return eval::MAX_PLY - (e.abs() - eval::EVAL_MATE);
+}
@@ -0,0 +1,189 @@
+use std::mem;
+use std::sync::atomic::{AtomicU64, Ordering};
+
+use crate::types::PackedMove;
+use crate::eval;
+
+pub const DEFAULT_HASH_MB: usize = 16;
+pub const MAX_HASH_MB: usize = 4096;
+
+// what the stored eval tells us about the real eval of the position
+#[derive(Debug, PartialEq, Copy, Clone)]
+pub enum Bound {
+    Exact,
+    Lower, // the search failed high, so the real eval is at least this
+    Upper // the search failed low, so the real eval is at most this
+}
+
+#[derive(Debug, PartialEq, Copy, Clone)]
+pub struct TTEntry {
+    pub key: u64,
+    pub best_move: Option<PackedMove>,
+    pub eval: i32,
+    pub depth: i16,
+    pub bound: Bound
+}
+
+// an entry packed into two words so every search thread can read and write it without locking
+// the key is stored xored with the data, so a slot that another thread was writing at the same time
+// no longer matches any key and just reads as a miss
+struct TTSlot {
+    key: AtomicU64,
+    data: AtomicU64
+}
+
+// shared by all search threads
+pub struct TranspositionTable {
+    slots: Vec<TTSlot>
+}
+
+impl TranspositionTable {
+    pub fn new(size_mb: usize) -> TranspositionTable {
+        let num_slots = ((size_mb.clamp(1, MAX_HASH_MB) * 1024 * 1024) / mem::size_of::<TTSlot>()).max(1);
+        return TranspositionTable {
+            slots: (0..num_slots).map(|_| TTSlot { key: AtomicU64::new(0), data: AtomicU64::new(0) }).collect()
+        };
+    }
+
+    pub fn clear(&self) {
+        for slot in self.slots.iter() {
+            slot.key.store(0, Ordering::Relaxed);
+            slot.data.store(0, Ordering::Relaxed);
+        }
+    }
+
+    // how full the table is in permille, estimated from the first thousand slots
+    pub fn hashfull(&self) -> usize {
+        let sample = &self.slots[..self.slots.len().min(1000)];
+        return sample.iter().filter(|s| s.data.load(Ordering::Relaxed) != 0).count() * 1000 / sample.len();
+    }
+
+    fn index(&self, key: u64) -> usize {
+        // maps the key onto the table without needing a power of two size
+        return (((key as u128) * (self.slots.len() as u128)) >> 64) as usize;
+    }
+
+    fn load(&self, idx: usize) -> Option<TTEntry> {
+        let slot = &self.slots[idx];
+        let data = slot.data.load(Ordering::Relaxed);
+        if data == 0 {
+            return None;
+        }
+        return Some(unpack_entry(slot.key.load(Ordering::Relaxed) ^ data, data));
+    }
+
+    // returns the entry for key, with mate scores made relative to the probing node at the given ply
+    pub fn probe(&self, key: u64, ply: i32) -> Option<TTEntry> {
+        return match self.load(self.index(key)) {
+            Some(entry) if entry.key == key => Some(TTEntry { eval: eval_from_tt(entry.eval, ply), ..entry }),
+            _ => None
+        };
+    }
+
+    pub fn store(&self, key: u64, best_move: Option<PackedMove>, eval: i32, depth: i32, bound: Bound, ply: i32) {
+        let idx = self.index(key);
+        let old_entry = self.load(idx);
+
+        // keep the deeper search result when two positions of the same key collide
+        // entries for other positions always get replaced, since older positions are less likely to come up again
+        if let Some(old) = old_entry {
+            if old.key == key && (old.depth as i32) > depth && bound != Bound::Exact {
+                return;
+            }
+        }
+
+        // keep the old best move if we didn't find one this time
+        let best_move = match (best_move, old_entry) {
+            (None, Some(old)) if old.key == key => old.best_move,
+            _ => best_move
+        };
+
+        let data = pack_entry(best_move, eval_to_tt(eval, ply), depth, bound);
+        let slot = &self.slots[idx];
+        slot.key.store(key ^ data, Ordering::Relaxed);
+        slot.data.store(data, Ordering::Relaxed);
+    }
+}
+
+// data layout: bits 0-15 move, 16-47 eval, 48-55 depth, 56-57 bound
+// a packed move is never zero, so zero move bits mean there's no best move
+// the bound is stored plus one so the data of an occupied slot is never zero
+fn pack_entry(best_move: Option<PackedMove>, eval: i32, depth: i32, bound: Bound) -> u64 {
+    let packed_move = best_move.map_or(0, |m| m.to_bits() as u64);
+    let packed_bound = match bound {
+        Bound::Exact => 1u64,
+        Bound::Lower => 2,
+        Bound::Upper => 3
+    };
+    return packed_move | ((eval as u32 as u64) << 16) | ((depth.clamp(i8::MIN as i32, i8::MAX as i32) as u8 as u64) << 48) | (packed_bound << 56);
+}
+
+fn unpack_entry(key: u64, data: u64) -> TTEntry {
+    let best_move = match (data & 0xFFFF) as u16 {
+        0 => None,
+        bits => Some(PackedMove::from_bits(bits))
+    };
+    let bound = match (data >> 56) & 0x3 {
+        1 => Bound::Exact,
+        2 => Bound::Lower,
+        _ => Bound::Upper
+    };
+    return TTEntry {
+        key,
+        best_move,
+        eval: (data >> 16) as u32 as i32,
+        depth: (data >> 48) as u8 as i8 as i16,
+        bound
+    };
+}
+
+// mate scores count the plies from the root, but a table entry can be reached from other plies,
+// so we store them relative to the node instead and convert them back when probing
+fn eval_to_tt(eval: i32, ply: i32) -> i32 {
+    if eval >= eval::EVAL_MATE {
+        return eval + ply;
+    } else if eval <= -eval::EVAL_MATE {
+        return eval - ply;
+    }
+    return eval;
+}
+
+fn eval_from_tt(eval: i32, ply: i32) -> i32 {
+    if eval >= eval::EVAL_MATE {
+        return eval - ply;
+    } else if eval <= -eval::EVAL_MATE {
+        return eval + ply;
+    }
+    return eval;
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::types::{Square, PROMOTION_TYPES};
+
+    #[test]
+    fn entry_keeps_move_and_flags() {
+        let mut moves = vec![
+            PackedMove::new(Square::E2, Square::E3, PackedMove::QUIET),
+            PackedMove::new(Square::E2, Square::E4, PackedMove::DOUBLE_PAWN_PUSH),
+            PackedMove::new(Square::E8, Square::G8, PackedMove::KING_CASTLE),
+            PackedMove::new(Square::E1, Square::C1, PackedMove::QUEEN_CASTLE),
+            PackedMove::new(Square::A1, Square::H8, PackedMove::CAPTURE),
+            PackedMove::new(Square::D5, Square::E6, PackedMove::ENPASSANT_CAPTURE)
+        ];
+        for &pt in PROMOTION_TYPES.iter() {
+            moves.push(PackedMove::new(Square::B7, Square::B8, PackedMove::promotion_flags(pt, false)));
+            moves.push(PackedMove::new(Square::H2, Square::G1, PackedMove::promotion_flags(pt, true)));
+        }
+        for &m in moves.iter() {
+            for &bound in [Bound::Exact, Bound::Lower, Bound::Upper].iter() {
+                let data = pack_entry(Some(m), -1234, 7, bound);
+                let entry = unpack_entry(42, data);
+                assert_eq!(entry, TTEntry { key: 42, best_move: Some(m), eval: -1234, depth: 7, bound });
+                assert_eq!(entry.best_move.unwrap().flags(), m.flags());
+            }
+        }
+        assert_eq!(unpack_entry(42, pack_entry(None, 0, 0, Bound::Upper)).best_move, None);
+    }
+}
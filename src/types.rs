@@ -1,6 +1,11 @@
 #![allow(dead_code)]
 
+use std::mem::MaybeUninit;
+use std::slice;
+
 use crate::bitboard;
+use crate::zobrist;
+use crate::move_search;
 
 // types, enums, structs
 
@@ -22,6 +27,8 @@ pub enum Direction {
     SW
 }
 
+// compass points rather than acronyms: two steps one way and one step the other
+#[allow(clippy::upper_case_acronyms)]
 #[derive(Debug, PartialEq, Copy, Clone)]
 pub enum KnightHop {
     NNW,
@@ -102,8 +109,9 @@ pub struct Board {
     pub turn: Color,
     pub castling_rights: CastlingRights,
     pub enpassant_files: bitboard::Bitrank,
-    pub all_piece_history: [bitboard::Bitboard; 16],
-    pub all_ptr: usize
+    pub halfmove_clock: u32, // plies since the last pawn move or capture
+    pub fullmove_number: u32, // starts at 1 and is incremented after black moves
+    pub hash: u64
 }
 
 #[derive(Debug, PartialEq, Copy, Clone)]
@@ -113,57 +121,349 @@ pub struct Move {
     pub promote_type: PieceType
 }
 
+// a move packed into 16 bits: the from square in bits 0-5, the to square in bits 6-11 and flags in bits 12-15
+// the flags say what kind of move it is, so making it doesn't have to work that out from the board
+// Move is the unpacked form, which doesn't need a board to parse from uci
+#[derive(PartialEq, Eq, PartialOrd, Ord, Hash, Copy, Clone)]
+pub struct PackedMove(u16);
+
+// a move and its score for move ordering
+#[derive(Debug, PartialEq, Copy, Clone)]
+pub struct ScoredMove {
+    pub m: PackedMove,
+    pub score: i32
+}
+
+// a list of moves kept on the stack, so generating moves at every node of the search never allocates
+// no legal position has more than 218 moves, so this always has room as long as from_fen keeps out positions with
+// more pieces than a real game can reach
+// the slots past len are left uninitialized, since filling all of them at every node costs more than generating the moves
+pub struct MoveList {
+    moves: [MaybeUninit<ScoredMove>; MAX_MOVES],
+    len: usize
+}
+
+#[derive(Debug, PartialEq, Copy, Clone)]
+pub struct HistoryEntry {
+    pub hash: u64,
+    pub irreversible: bool // true if this position was reached by an irreversible move
+}
+
+// the hashes of every position in the game so far, followed by the positions on the current search path
+#[derive(Debug, Clone)]
+pub struct History {
+    pub entries: Vec<HistoryEntry>,
+    pub root: usize // index of the position the current search started from
+}
+
+#[derive(Debug, PartialEq, Copy, Clone)]
+pub enum FenError {
+    MissingField,
+    InvalidPiecePlacement,
+    InvalidKingCount,
+    InvalidPieceCount,
+    InvalidTurn,
+    InvalidPosition,
+    InvalidCastlingRights,
+    InvalidEnpassant,
+    InvalidClock
+}
+
 // constants
 
 pub const KING_DIRECTIONS: [Direction; 8] = [Direction::N, Direction::S, Direction::E, Direction::W, Direction::NW, Direction::NE, Direction::SW, Direction::SE];
 pub const QUEEN_DIRECTIONS: [Direction; 8] = [Direction::N, Direction::S, Direction::E, Direction::W, Direction::NW, Direction::NE, Direction::SW, Direction::SE];
 pub const ROOK_DIRECTIONS: [Direction; 4] = [Direction::N, Direction::S, Direction::E, Direction::W];
 pub const BISHOP_DIRECTIONS: [Direction; 4] = [Direction::NW, Direction::NE, Direction::SW, Direction::SE];
+pub const MAX_MOVES: usize = 256;
+pub const PROMOTION_TYPES: [PieceType; 4] = [PieceType::Queen, PieceType::Rook, PieceType::Bishop, PieceType::Knight];
 pub const KNIGHT_HOPS: [KnightHop; 8] = [KnightHop::NNW, KnightHop::NNE, KnightHop::NWW, KnightHop::NEE, KnightHop::SSW, KnightHop::SSE, KnightHop::SWW, KnightHop::SEE];
 
 // Functions
 impl Board {
+    pub fn from_fen(fen: &str) -> Result<Board, FenError> {
+        let mut fields = fen.split_whitespace();
+
+        let placement = fields.next().ok_or(FenError::MissingField)?;
+        let turn = fields.next().ok_or(FenError::MissingField)?;
+        let castling = fields.next().ok_or(FenError::MissingField)?;
+        let enpassant = fields.next().ok_or(FenError::MissingField)?;
+
+        let mut white = bitboard::Pieces { all: 0, king: 0, queens: 0, rooks: 0, bishops: 0, knights: 0, pawns: 0 };
+        let mut black = bitboard::Pieces { all: 0, king: 0, queens: 0, rooks: 0, bishops: 0, knights: 0, pawns: 0 };
+
+        // fen lists ranks from 8 down to 1, and files from a to h within each rank
+        let ranks: Vec<&str> = placement.split('/').collect();
+        if ranks.len() != 8 {
+            return Err(FenError::InvalidPiecePlacement);
+        }
+        for (i, rank) in ranks.iter().enumerate() {
+            let rank_idx = 7 - i;
+            let mut file_idx = 0;
+            for c in rank.chars() {
+                if let Some(skip) = c.to_digit(10) {
+                    if skip == 0 || skip > 8 {
+                        return Err(FenError::InvalidPiecePlacement);
+                    }
+                    file_idx += skip as usize;
+                    continue;
+                }
+                if file_idx >= 8 {
+                    return Err(FenError::InvalidPiecePlacement);
+                }
+                let bb = bitboard::bitboard_from_index((rank_idx*8 + file_idx) as u32);
+                let pieces = if c.is_ascii_uppercase() { &mut white } else { &mut black };
+                match PieceType::from_char(c) {
+                    PieceType::King => pieces.king |= bb,
+                    PieceType::Queen => pieces.queens |= bb,
+                    PieceType::Rook => pieces.rooks |= bb,
+                    PieceType::Bishop => pieces.bishops |= bb,
+                    PieceType::Knight => pieces.knights |= bb,
+                    PieceType::Pawn => pieces.pawns |= bb,
+                    PieceType::Null => return Err(FenError::InvalidPiecePlacement)
+                }
+                file_idx += 1;
+            }
+            if file_idx != 8 {
+                return Err(FenError::InvalidPiecePlacement);
+            }
+        }
+        white.all = white.king | white.queens | white.rooks | white.bishops | white.knights | white.pawns;
+        black.all = black.king | black.queens | black.rooks | black.bishops | black.knights | black.pawns;
+
+        // the rest of the engine assumes exactly one king per side
+        if white.king.count_ones() != 1 || black.king.count_ones() != 1 {
+            return Err(FenError::InvalidKingCount);
+        }
+        // move lists only have room for the moves of positions a real game can reach, so each side can have
+        // at most 8 pawns, and only as many extra pieces as it has pawns missing to have promoted them
+        for pieces in [&white, &black].iter() {
+            let extra_pieces = (pieces.queens.count_ones() as i32 - 1).max(0)
+                + (pieces.rooks.count_ones() as i32 - 2).max(0)
+                + (pieces.bishops.count_ones() as i32 - 2).max(0)
+                + (pieces.knights.count_ones() as i32 - 2).max(0);
+            if extra_pieces > 8 - pieces.pawns.count_ones() as i32 {
+                return Err(FenError::InvalidPieceCount);
+            }
+        }
+
+        let turn = match turn {
+            "w" => Color::White,
+            "b" => Color::Black,
+            _ => return Err(FenError::InvalidTurn)
+        };
+
+        let mut castling_rights = CastlingRights {
+            white_long: false,
+            white_short: false,
+            black_long: false,
+            black_short: false
+        };
+        if castling != "-" {
+            for c in castling.chars() {
+                match c {
+                    'K' => castling_rights.white_short = true,
+                    'Q' => castling_rights.white_long = true,
+                    'k' => castling_rights.black_short = true,
+                    'q' => castling_rights.black_long = true,
+                    _ => return Err(FenError::InvalidCastlingRights)
+                }
+            }
+        }
+        // like the enpassant square, we drop any right whose king or rook isn't on its starting square,
+        // since castling would then move pieces that aren't there
+        let white_king_home = (white.king & Square::E1.to_bitboard()) != 0;
+        let black_king_home = (black.king & Square::E8.to_bitboard()) != 0;
+        castling_rights.white_short &= white_king_home && (white.rooks & Square::H1.to_bitboard()) != 0;
+        castling_rights.white_long &= white_king_home && (white.rooks & Square::A1.to_bitboard()) != 0;
+        castling_rights.black_short &= black_king_home && (black.rooks & Square::H8.to_bitboard()) != 0;
+        castling_rights.black_long &= black_king_home && (black.rooks & Square::A8.to_bitboard()) != 0;
+
+        // the enpassant target square has to be right behind an enemy pawn that could just have double pushed,
+        // so it and the square the pawn came from must be empty
+        // like apply_move, we only keep the flag if one of our pawns is next to that pawn, otherwise the capture is
+        // impossible and the position would hash differently from the same position reached by moves
+        let mut enpassant_files = bitboard::EMPTY_BITRANK;
+        if enpassant != "-" {
+            let sq = Square::from_uci(enpassant).ok_or(FenError::InvalidEnpassant)?;
+            let (expected_rank, forward, our_pawns, their_pawns) = if turn == Color::White {
+                (Rank::Rank6, Direction::S, white.pawns, black.pawns)
+            } else {
+                (Rank::Rank3, Direction::N, black.pawns, white.pawns)
+            };
+            if sq.get_rank() != expected_rank {
+                return Err(FenError::InvalidEnpassant);
+            }
+            let target_bb = sq.to_bitboard();
+            let pushed_bb = bitboard::slide1(target_bb, forward);
+            let origin_bb = bitboard::slide1(target_bb, forward.mirror());
+            if (pushed_bb & their_pawns) == 0 || ((target_bb | origin_bb) & (white.all | black.all)) != 0 {
+                return Err(FenError::InvalidEnpassant);
+            }
+            if ((bitboard::slide1(pushed_bb, Direction::E) | bitboard::slide1(pushed_bb, Direction::W)) & our_pawns) != 0 {
+                enpassant_files = (0x1u8) << (sq.to_index() % 8);
+            }
+        }
+
+        // the move clocks are optional since some guis leave them out
+        let halfmove_clock = match fields.next() {
+            Some(field) => field.parse::<u32>().map_err(|_| FenError::InvalidClock)?,
+            None => 0
+        };
+        let fullmove_number = match fields.next() {
+            Some(field) => field.parse::<u32>().map_err(|_| FenError::InvalidClock)?,
+            None => 1
+        };
+
+        let mut board = Board {
+            white_bitboard_pieces: white,
+            black_bitboard_pieces: black,
+            turn,
+            castling_rights,
+            enpassant_files,
+            halfmove_clock,
+            fullmove_number,
+            hash: 0u64
+        };
+        board.hash = zobrist::hash_board(&board);
+
+        // the side that just moved can't have left its own king in check, and if it did we could capture the king,
+        // which the rest of the engine isn't built to handle
+        let their_king_idx = board.get_pieces(turn.opposite()).king.trailing_zeros() as usize;
+        if move_search::attackers_to(&board, their_king_idx, turn) != 0 {
+            return Err(FenError::InvalidPosition);
+        }
+
+        return Ok(board);
+    }
+
+    pub fn to_fen(self) -> String {
+        let mut fen = String::new();
+
+        for rank_idx in (0..8).rev() {
+            let mut empty = 0;
+            for file_idx in 0..8 {
+                let (p, color) = self.get_piece(rank_idx*8 + file_idx);
+                if p == PieceType::Null {
+                    empty += 1;
+                    continue;
+                }
+                if empty > 0 {
+                    fen.push_str(&empty.to_string());
+                    empty = 0;
+                }
+                fen.push(if color == Color::White { p.to_char().to_ascii_uppercase() } else { p.to_char() });
+            }
+            if empty > 0 {
+                fen.push_str(&empty.to_string());
+            }
+            if rank_idx > 0 {
+                fen.push('/');
+            }
+        }
+
+        fen.push_str(if self.turn == Color::White { " w " } else { " b " });
+
+        let castling_start = fen.len();
+        if self.castling_rights.white_short {
+            fen.push('K');
+        }
+        if self.castling_rights.white_long {
+            fen.push('Q');
+        }
+        if self.castling_rights.black_short {
+            fen.push('k');
+        }
+        if self.castling_rights.black_long {
+            fen.push('q');
+        }
+        if fen.len() == castling_start {
+            fen.push('-');
+        }
+
+        if self.enpassant_files == bitboard::EMPTY_BITRANK {
+            fen.push_str(" -");
+        } else {
+            let file_idx = self.enpassant_files.trailing_zeros() as usize;
+            let rank_idx = if self.turn == Color::White { 5 } else { 2 };
+            fen.push(' ');
+            fen.push_str(Square::from_index((rank_idx*8 + file_idx) as u32).unwrap().to_uci());
+        }
+
+        fen.push_str(&format!(" {} {}", self.halfmove_clock, self.fullmove_number));
+
+        return fen;
+    }
+
+    pub fn get_pieces(&self, c: Color) -> &bitboard::Pieces {
+        return if c == Color::White { &self.white_bitboard_pieces } else { &self.black_bitboard_pieces };
+    }
+
+    // returns the piece and its color on the square with index idx; empty squares are PieceType::Null
+    pub fn get_piece(&self, idx: usize) -> (PieceType, Color) {
+        let bb = bitboard::bitboard_from_index(idx as u32);
+        for &(pieces, color) in [(&self.white_bitboard_pieces, Color::White), (&self.black_bitboard_pieces, Color::Black)].iter() {
+            if (pieces.all & bb) == 0 {
+                continue;
+            }
+            if (pieces.king & bb) != 0 {
+                return (PieceType::King, color);
+            } else if (pieces.queens & bb) != 0 {
+                return (PieceType::Queen, color);
+            } else if (pieces.rooks & bb) != 0 {
+                return (PieceType::Rook, color);
+            } else if (pieces.bishops & bb) != 0 {
+                return (PieceType::Bishop, color);
+            } else if (pieces.knights & bb) != 0 {
+                return (PieceType::Knight, color);
+            } else if (pieces.pawns & bb) != 0 {
+                return (PieceType::Pawn, color);
+            }
+        }
+        return (PieceType::Null, Color::White);
+    }
+
     pub fn pretty_print(&self) {
         let mut pieces: [(PieceType, Color); 64] = [(PieceType::Null, Color::White); 64];
 
         {
             let bitboard = self.white_bitboard_pieces.king;
             if bitboard != 0 {
-                pieces[(bitboard.trailing_zeros() as usize)] = (PieceType::King, Color::White);
+                pieces[bitboard.trailing_zeros() as usize] = (PieceType::King, Color::White);
             }
         }
         {
             let mut bitboard = self.white_bitboard_pieces.queens;
             while bitboard != 0 {
-                pieces[(bitboard.trailing_zeros() as usize)] = (PieceType::Queen, Color::White);
+                pieces[bitboard.trailing_zeros() as usize] = (PieceType::Queen, Color::White);
                 bitboard &= !(0x1 << bitboard.trailing_zeros());
             }
         }
         {
             let mut bitboard = self.white_bitboard_pieces.rooks;
             while bitboard != 0 {
-                pieces[(bitboard.trailing_zeros() as usize)] = (PieceType::Rook, Color::White);
+                pieces[bitboard.trailing_zeros() as usize] = (PieceType::Rook, Color::White);
                 bitboard &= !(0x1 << bitboard.trailing_zeros());
             }
         }
         {
             let mut bitboard = self.white_bitboard_pieces.bishops;
             while bitboard != 0 {
-                pieces[(bitboard.trailing_zeros() as usize)] = (PieceType::Bishop, Color::White);
+                pieces[bitboard.trailing_zeros() as usize] = (PieceType::Bishop, Color::White);
                 bitboard &= !(0x1 << bitboard.trailing_zeros());
             }
         }
         {
             let mut bitboard = self.white_bitboard_pieces.knights;
             while bitboard != 0 {
-                pieces[(bitboard.trailing_zeros() as usize)] = (PieceType::Knight, Color::White);
+                pieces[bitboard.trailing_zeros() as usize] = (PieceType::Knight, Color::White);
                 bitboard &= !(0x1 << bitboard.trailing_zeros());
             }
         }
         {
             let mut bitboard = self.white_bitboard_pieces.pawns;
             while bitboard != 0 {
-                pieces[(bitboard.trailing_zeros() as usize)] = (PieceType::Pawn, Color::White);
+                pieces[bitboard.trailing_zeros() as usize] = (PieceType::Pawn, Color::White);
                 bitboard &= !(0x1 << bitboard.trailing_zeros());
             }
         }
@@ -171,90 +471,82 @@ impl Board {
         {
             let bitboard = self.black_bitboard_pieces.king;
             if bitboard != 0 {
-                pieces[(bitboard.trailing_zeros() as usize)] = (PieceType::King, Color::Black);
+                pieces[bitboard.trailing_zeros() as usize] = (PieceType::King, Color::Black);
             }
         }
         {
             let mut bitboard = self.black_bitboard_pieces.queens;
             while bitboard != 0 {
-                pieces[(bitboard.trailing_zeros() as usize)] = (PieceType::Queen, Color::Black);
+                pieces[bitboard.trailing_zeros() as usize] = (PieceType::Queen, Color::Black);
                 bitboard &= !(0x1 << bitboard.trailing_zeros());
             }
         }
         {
             let mut bitboard = self.black_bitboard_pieces.rooks;
             while bitboard != 0 {
-                pieces[(bitboard.trailing_zeros() as usize)] = (PieceType::Rook, Color::Black);
+                pieces[bitboard.trailing_zeros() as usize] = (PieceType::Rook, Color::Black);
                 bitboard &= !(0x1 << bitboard.trailing_zeros());
             }
         }
         {
             let mut bitboard = self.black_bitboard_pieces.bishops;
             while bitboard != 0 {
-                pieces[(bitboard.trailing_zeros() as usize)] = (PieceType::Bishop, Color::Black);
+                pieces[bitboard.trailing_zeros() as usize] = (PieceType::Bishop, Color::Black);
                 bitboard &= !(0x1 << bitboard.trailing_zeros());
             }
         }
         {
             let mut bitboard = self.black_bitboard_pieces.knights;
             while bitboard != 0 {
-                pieces[(bitboard.trailing_zeros() as usize)] = (PieceType::Knight, Color::Black);
+                pieces[bitboard.trailing_zeros() as usize] = (PieceType::Knight, Color::Black);
                 bitboard &= !(0x1 << bitboard.trailing_zeros());
             }
         }
         {
             let mut bitboard = self.black_bitboard_pieces.pawns;
             while bitboard != 0 {
-                pieces[(bitboard.trailing_zeros() as usize)] = (PieceType::Pawn, Color::Black);
+                pieces[bitboard.trailing_zeros() as usize] = (PieceType::Pawn, Color::Black);
                 bitboard &= !(0x1 << bitboard.trailing_zeros());
             }
         }
 
         print!("8   ");
-        for i in 56..64 {
-            let (p, color) = pieces[i];
+        for &(p, color) in pieces[56..64].iter() {
             let c = if color == Color::White { p.to_char() } else { p.to_char().to_ascii_uppercase() };
             print!("{} ", c);
         }
         print!("\n7   ");
-        for i in 48..56 {
-            let (p, color) = pieces[i];
+        for &(p, color) in pieces[48..56].iter() {
             let c = if color == Color::White { p.to_char() } else { p.to_char().to_ascii_uppercase() };
             print!("{} ", c);
         }
         print!("\n6   ");
-        for i in 40..48 {
-            let (p, color) = pieces[i];
+        for &(p, color) in pieces[40..48].iter() {
             let c = if color == Color::White { p.to_char() } else { p.to_char().to_ascii_uppercase() };
             print!("{} ", c);
         }
         print!("\n5   ");
-        for i in 32..40 {
-            let (p, color) = pieces[i];
+        for &(p, color) in pieces[32..40].iter() {
             let c = if color == Color::White { p.to_char() } else { p.to_char().to_ascii_uppercase() };
             print!("{} ", c);
         }
         print!("\n4   ");
-        for i in 24..32 {
-            let (p, color) = pieces[i];
+        for &(p, color) in pieces[24..32].iter() {
             let c = if color == Color::White { p.to_char() } else { p.to_char().to_ascii_uppercase() };
             print!("{} ", c);
         }
         print!("\n3   ");
-        for i in 16..24 {
-            let (p, color) = pieces[i];
+        for &(p, color) in pieces[16..24].iter() {
             let c = if color == Color::White { p.to_char() } else { p.to_char().to_ascii_uppercase() };
             print!("{} ", c);
         }
         print!("\n2   ");
-        for i in 8..16 {
-            let (p, color) = pieces[i];
+        for &(p, color) in pieces[8..16].iter() {
             let c = if color == Color::White { p.to_char() } else { p.to_char().to_ascii_uppercase() };
             print!("{} ", c);
         }
         print!("\n1   ");
-        for i in 0..8 {
-            let (p, color) = pieces[i];
+        for &(p, color) in pieces[0..8].iter() {
             let c = if color == Color::White { p.to_char() } else { p.to_char().to_ascii_uppercase() };
             print!("{} ", c);
         }
@@ -262,9 +554,127 @@ impl Board {
     }
 }
 
+impl History {
+    pub fn new(b: &Board) -> History {
+        return History {
+            entries: vec![HistoryEntry {hash: b.hash, irreversible: true}],
+            root: 0
+        };
+    }
+
+    pub fn push(&mut self, b: &Board, irreversible: bool) {
+        self.entries.push(HistoryEntry {hash: b.hash, irreversible});
+    }
+
+    pub fn pop(&mut self) {
+        self.entries.pop();
+    }
+
+    // marks the last position as the root of a new search
+    pub fn set_root(&mut self) {
+        self.root = self.entries.len() - 1;
+    }
+
+    // returns true if the last position is a draw by repetition
+    // a position that repeats once inside the search is treated as a draw already, since if repeating was
+    // the best either side could do the first time, it will be the best they can do the second time too
+    // repeats of positions from before the search root need to happen twice to be a draw (threefold repetition)
+    pub fn is_repetition(&self) -> bool {
+        let last = self.entries.len() - 1;
+        let hash = self.entries[last].hash;
+        let mut count = 0;
+
+        // only positions with the same side to move can repeat, so step back two plies at a time
+        // we can stop at the last irreversible move since no earlier position can repeat past it
+        let mut idx = last;
+        while idx >= 2 && !self.entries[idx].irreversible && !self.entries[idx-1].irreversible {
+            idx -= 2;
+            if self.entries[idx].hash == hash {
+                if idx > self.root {
+                    return true;
+                }
+                count += 1;
+                if count >= 2 {
+                    return true;
+                }
+            }
+        }
+        return false;
+    }
+}
+
+impl MoveList {
+    pub fn new() -> MoveList {
+        return MoveList {
+            moves: [MaybeUninit::uninit(); MAX_MOVES],
+            len: 0
+        };
+    }
+
+    pub fn push(&mut self, m: PackedMove) {
+        debug_assert!(self.len < MAX_MOVES, "move list is full");
+        self.moves[self.len] = MaybeUninit::new(ScoredMove {m, score: 0});
+        self.len += 1;
+    }
+
+    pub fn len(&self) -> usize {
+        return self.len;
+    }
+
+    pub fn is_empty(&self) -> bool {
+        return self.len == 0;
+    }
+
+    pub fn contains(&self, m: &PackedMove) -> bool {
+        return self.iter().any(|x| x == *m);
+    }
+
+    pub fn iter(&self) -> impl Iterator<Item = PackedMove> + '_ {
+        return self.scored_moves().iter().map(|sm| sm.m);
+    }
+
+    pub fn scored_moves(&self) -> &[ScoredMove] {
+        // SAFETY: push initialized every slot before len, and ScoredMove has the same layout as MaybeUninit<ScoredMove>
+        return unsafe { slice::from_raw_parts(self.moves.as_ptr() as *const ScoredMove, self.len) };
+    }
+
+    pub fn scored_moves_mut(&mut self) -> &mut [ScoredMove] {
+        // SAFETY: as in scored_moves; writing through the slice can't uninitialize a slot
+        return unsafe { slice::from_raw_parts_mut(self.moves.as_mut_ptr() as *mut ScoredMove, self.len) };
+    }
+
+    // drops the moves for which keep returns false, keeping the rest in order
+    pub fn retain(&mut self, keep: impl Fn(&ScoredMove) -> bool) {
+        let moves = self.scored_moves_mut();
+        let mut kept = 0;
+        for i in 0..moves.len() {
+            if keep(&moves[i]) {
+                moves[kept] = moves[i];
+                kept += 1;
+            }
+        }
+        self.len = kept;
+    }
+
+    // highest score first; moves with the same score stay in the order they were generated
+    // an insertion sort, since the standard library's stable sort may allocate and these lists are short
+    pub fn sort_by_score(&mut self) {
+        let moves = self.scored_moves_mut();
+        for i in 1..moves.len() {
+            let sm = moves[i];
+            let mut j = i;
+            while j > 0 && moves[j-1].score < sm.score {
+                moves[j] = moves[j-1];
+                j -= 1;
+            }
+            moves[j] = sm;
+        }
+    }
+}
+
 impl Square {
     pub fn from_uci(s: &str) -> Option<Square> {
-        return match (s.to_uppercase().as_str()) {
+        return match s.to_uppercase().as_str() {
             "A1" => Some(Square::A1),
             "B1" => Some(Square::B1),
             "C1" => Some(Square::C1),
@@ -333,7 +743,7 @@ impl Square {
         }
     }
 
-    pub fn to_uci(&self) -> &str {
+    pub fn to_uci(self) -> &'static str {
         return match self {
             Square::A1 => "a1",
             Square::B1 => "b1",
@@ -402,7 +812,7 @@ impl Square {
         }
     }
 
-    pub fn to_index(&self) -> usize {
+    pub fn to_index(self) -> usize {
         return match self {
             Square::A1 => 0x00usize,
             Square::B1 => 0x01usize,
@@ -681,8 +1091,8 @@ impl Square {
 
     pub fn slide(&self, dir: Direction, dist: i32) -> Option<Square> {
         let mut cur = Some(*self);
-        for i in 1..(dist+1) {
-            match (cur) {
+        for _ in 1..(dist+1) {
+            match cur {
                 Some(x) => {
                     cur = match (x, dir) {
                         (Square::A1, Direction::N) => Some(Square::A2),
@@ -1209,7 +1619,7 @@ impl Square {
 
     pub fn knight_hop(&self, kh : KnightHop) -> Option<Square> {
         let mut cur = Some(*self);
-        match (cur) {
+        match cur {
             Some(x) => {
                 cur = match (x, kh) {
                     (Square::A1, KnightHop::NNW) => None,
@@ -1734,7 +2144,7 @@ impl Square {
     }
 
     pub fn mirror(&self) -> Square {
-        return match (self) {
+        return match self {
             Square::A1 => Square::A8,
             Square::B1 => Square::B8,
             Square::C1 => Square::C8,
@@ -1810,7 +2220,7 @@ impl Square {
         }
     }
 
-    pub fn to_bitboard(&self) -> bitboard::Bitboard {
+    pub fn to_bitboard(self) -> bitboard::Bitboard {
         return match self {
             Square::A1 => bitboard::SQUARE_A1,
             Square::B1 => bitboard::SQUARE_B1,
@@ -1880,15 +2290,26 @@ impl Square {
     }
 }
 
+impl Color {
+    pub fn opposite(&self) -> Color {
+        return if *self == Color::White { Color::Black } else { Color::White };
+    }
+}
+
 impl Move {
     pub fn from_uci(m: &str) -> Option<Move> {
-        let mlen = m.len();
-        if mlen < 4 {
+        // checking for ascii first means slicing by bytes below can't split a character
+        if !m.is_ascii() || (m.len() != 4 && m.len() != 5) {
             return None;
         }
         let fs = Square::from_uci(&m[0..2]);
         let ts = Square::from_uci(&m[2..4]);
-        let pt = if mlen >= 5 { PieceType::from_char(m.chars().nth(4).unwrap()) } else { PieceType::Null };
+        let pt = match &m[4..] {
+            "" => PieceType::Null,
+            // only q/r/b/n are valid promotion suffixes
+            "q" | "r" | "b" | "n" => PieceType::from_char(m.as_bytes()[4] as char),
+            _ => return None
+        };
         return match (fs, ts) {
             (Some(x), Some(y)) => Some(Move {
                 from_square: x,
@@ -1899,7 +2320,7 @@ impl Move {
         }
     }
 
-    pub fn to_uci(&self) -> String {
+    pub fn to_uci(self) -> String {
         let fs = self.from_square.to_uci();
         let ts = self.to_square.to_uci();
         if self.promote_type == PieceType::Null {
@@ -1910,6 +2331,139 @@ impl Move {
     }
 }
 
+impl PackedMove {
+    // flags; captures have the CAPTURE bit set and promotions the PROMOTION bit,
+    // with the piece promoted to in the low two bits (knight, bishop, rook, queen)
+    pub const QUIET: u16 = 0;
+    pub const DOUBLE_PAWN_PUSH: u16 = 1;
+    pub const KING_CASTLE: u16 = 2;
+    pub const QUEEN_CASTLE: u16 = 3;
+    pub const CAPTURE: u16 = 4;
+    pub const ENPASSANT_CAPTURE: u16 = 5;
+    pub const PROMOTION: u16 = 8;
+
+    pub fn new(from_square: Square, to_square: Square, flags: u16) -> PackedMove {
+        return PackedMove((from_square.to_index() as u16) | ((to_square.to_index() as u16) << 6) | (flags << 12));
+    }
+
+    // flags for a promotion to pt, which also captures if capture is set
+    pub fn promotion_flags(pt: PieceType, capture: bool) -> u16 {
+        let piece_bits = match pt {
+            PieceType::Knight => 0,
+            PieceType::Bishop => 1,
+            PieceType::Rook => 2,
+            _ => 3
+        };
+        return PackedMove::PROMOTION | piece_bits | if capture { PackedMove::CAPTURE } else { 0 };
+    }
+
+    // works out the flags of m from the position it's played in, which m has to be legal in
+    // apply_move trusts these flags, so moves we haven't generated ourselves should be matched against calc_moves instead
+    pub fn from_move(b: &Board, m: Move) -> PackedMove {
+        let (piece_type, _) = b.get_piece(m.from_square.to_index());
+        let (captured_type, captured_color) = b.get_piece(m.to_square.to_index());
+        let is_capture = captured_type != PieceType::Null && captured_color != b.turn;
+        let file_diff = (m.to_square.to_index() % 8) as i32 - (m.from_square.to_index() % 8) as i32;
+        let rank_diff = (m.to_square.to_index() / 8) as i32 - (m.from_square.to_index() / 8) as i32;
+
+        let flags = if m.promote_type != PieceType::Null {
+            PackedMove::promotion_flags(m.promote_type, is_capture)
+        } else if is_capture {
+            PackedMove::CAPTURE
+        } else if piece_type == PieceType::Pawn && file_diff != 0 {
+            // a pawn moving diagonally to an empty square must be capturing enpassant
+            PackedMove::ENPASSANT_CAPTURE
+        } else if piece_type == PieceType::Pawn && rank_diff.abs() == 2 {
+            PackedMove::DOUBLE_PAWN_PUSH
+        } else if piece_type == PieceType::King && file_diff == 2 {
+            PackedMove::KING_CASTLE
+        } else if piece_type == PieceType::King && file_diff == -2 {
+            PackedMove::QUEEN_CASTLE
+        } else {
+            PackedMove::QUIET
+        };
+        return PackedMove::new(m.from_square, m.to_square, flags);
+    }
+
+    pub fn from_uci(b: &Board, m: &str) -> Option<PackedMove> {
+        return Move::from_uci(m).map(|m| PackedMove::from_move(b, m));
+    }
+
+    pub fn to_move(self) -> Move {
+        return Move {from_square: self.from_square(), to_square: self.to_square(), promote_type: self.promote_type()};
+    }
+
+    pub fn to_uci(self) -> String {
+        return self.to_move().to_uci();
+    }
+
+    // the raw 16 bits, which are never zero for a real move since its squares differ
+    pub fn to_bits(self) -> u16 {
+        return self.0;
+    }
+
+    pub fn from_bits(bits: u16) -> PackedMove {
+        return PackedMove(bits);
+    }
+
+    // named after the Move field it stands in for, not a constructor
+    #[allow(clippy::wrong_self_convention)]
+    pub fn from_square(self) -> Square {
+        return Square::from_index((self.0 & 0x3F) as u32).unwrap();
+    }
+
+    pub fn to_square(self) -> Square {
+        return Square::from_index(((self.0 >> 6) & 0x3F) as u32).unwrap();
+    }
+
+    pub fn flags(&self) -> u16 {
+        return self.0 >> 12;
+    }
+
+    pub fn is_capture(&self) -> bool {
+        return (self.flags() & PackedMove::CAPTURE) != 0;
+    }
+
+    pub fn is_promotion(&self) -> bool {
+        return (self.flags() & PackedMove::PROMOTION) != 0;
+    }
+
+    // neither a capture nor a promotion
+    pub fn is_quiet(&self) -> bool {
+        return (self.flags() & (PackedMove::CAPTURE | PackedMove::PROMOTION)) == 0;
+    }
+
+    pub fn is_enpassant(&self) -> bool {
+        return self.flags() == PackedMove::ENPASSANT_CAPTURE;
+    }
+
+    pub fn is_double_pawn_push(&self) -> bool {
+        return self.flags() == PackedMove::DOUBLE_PAWN_PUSH;
+    }
+
+    pub fn is_castle(&self) -> bool {
+        return self.flags() == PackedMove::KING_CASTLE || self.flags() == PackedMove::QUEEN_CASTLE;
+    }
+
+    pub fn promote_type(&self) -> PieceType {
+        if !self.is_promotion() {
+            return PieceType::Null;
+        }
+        return match self.flags() & 0x3 {
+            0 => PieceType::Knight,
+            1 => PieceType::Bishop,
+            2 => PieceType::Rook,
+            _ => PieceType::Queen
+        };
+    }
+}
+
+impl std::fmt::Debug for PackedMove {
+    fn fmt(&self, f: &mut std::fmt::Formatter) -> std::fmt::Result {
+        return write!(f, "{} (flags {})", self.to_uci(), self.flags());
+    }
+}
+
 impl PieceType {
     pub fn from_char(c: char) -> PieceType {
         return match c {
@@ -1929,7 +2483,7 @@ impl PieceType {
         }
     }
 
-    pub fn to_char(&self) -> char {
+    pub fn to_char(self) -> char {
         return match self {
             PieceType::King => 'k',
             PieceType::Queen => 'q',
@@ -2000,4 +2554,143 @@ impl KnightHop {
             KnightHop::SEE => KnightHop::NWW
         }
     }
-}
\ No newline at end of file
+}
+
+impl std::fmt::Display for FenError {
+    fn fmt(&self, f: &mut std::fmt::Formatter) -> std::fmt::Result {
+        let msg = match self {
+            FenError::MissingField => "missing field",
+            FenError::InvalidPiecePlacement => "invalid piece placement",
+            FenError::InvalidKingCount => "each side must have exactly one king",
+            FenError::InvalidPieceCount => "more pieces than a side can have",
+            FenError::InvalidTurn => "invalid side to move",
+            FenError::InvalidPosition => "the side that just moved is in check",
+            FenError::InvalidCastlingRights => "invalid castling rights",
+            FenError::InvalidEnpassant => "invalid enpassant square",
+            FenError::InvalidClock => "invalid move clock"
+        };
+        return write!(f, "{}", msg);
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::move_search;
+    use crate::utils;
+
+    // plays the legal move with the given uci string
+    fn play(b: &Board, m_str: &str) -> Board {
+        let m = move_search::calc_moves(b).iter().find(|m| m.to_uci() == m_str).unwrap();
+        return utils::apply_move(b, m);
+    }
+
+    // every flag a generated move can have
+    fn all_flags() -> Vec<u16> {
+        let mut flags = vec![PackedMove::QUIET, PackedMove::DOUBLE_PAWN_PUSH, PackedMove::KING_CASTLE, PackedMove::QUEEN_CASTLE,
+            PackedMove::CAPTURE, PackedMove::ENPASSANT_CAPTURE];
+        for &pt in PROMOTION_TYPES.iter() {
+            flags.push(PackedMove::promotion_flags(pt, false));
+            flags.push(PackedMove::promotion_flags(pt, true));
+        }
+        return flags;
+    }
+
+    #[test]
+    fn packed_move_round_trips() {
+        for from_idx in 0..64 {
+            for to_idx in 0..64 {
+                for &flags in all_flags().iter() {
+                    let (from_square, to_square) = (Square::from_index(from_idx).unwrap(), Square::from_index(to_idx).unwrap());
+                    let m = PackedMove::new(from_square, to_square, flags);
+                    assert_eq!((m.from_square(), m.to_square(), m.flags()), (from_square, to_square, flags));
+                    assert_eq!(PackedMove::from_bits(m.to_bits()), m);
+                }
+            }
+        }
+    }
+
+    #[test]
+    fn packed_move_flags() {
+        for &flags in all_flags().iter() {
+            let m = PackedMove::new(Square::E7, Square::D8, flags);
+            assert_eq!(m.is_capture(), flags == PackedMove::CAPTURE || flags == PackedMove::ENPASSANT_CAPTURE
+                || (flags >= PackedMove::PROMOTION && (flags & PackedMove::CAPTURE) != 0), "{:?}", m);
+            assert_eq!(m.is_promotion(), flags >= PackedMove::PROMOTION, "{:?}", m);
+            assert_eq!(m.is_quiet(), !m.is_capture() && !m.is_promotion(), "{:?}", m);
+            assert_eq!(m.is_enpassant(), flags == PackedMove::ENPASSANT_CAPTURE, "{:?}", m);
+            assert_eq!(m.is_double_pawn_push(), flags == PackedMove::DOUBLE_PAWN_PUSH, "{:?}", m);
+            assert_eq!(m.is_castle(), flags == PackedMove::KING_CASTLE || flags == PackedMove::QUEEN_CASTLE, "{:?}", m);
+        }
+        for &pt in PROMOTION_TYPES.iter() {
+            for &capture in [false, true].iter() {
+                let m = PackedMove::new(Square::E7, Square::D8, PackedMove::promotion_flags(pt, capture));
+                assert_eq!((m.promote_type(), m.is_capture()), (pt, capture));
+                assert_eq!(m.to_move(), Move {from_square: Square::E7, to_square: Square::D8, promote_type: pt});
+            }
+        }
+        assert_eq!(PackedMove::new(Square::E2, Square::E4, PackedMove::DOUBLE_PAWN_PUSH).promote_type(), PieceType::Null);
+    }
+
+    #[test]
+    fn move_from_uci() {
+        assert_eq!(Move::from_uci("e2e4"), Some(Move {from_square: Square::E2, to_square: Square::E4, promote_type: PieceType::Null}));
+        assert_eq!(Move::from_uci("e7e8n"), Some(Move {from_square: Square::E7, to_square: Square::E8, promote_type: PieceType::Knight}));
+        for bad in ["e7e8x", "e7e8k", "e7e8Q", "e7e8qq", "e2e4junk", "e2e", "e2é4", "é2e4", "i2e4"].iter() {
+            assert_eq!(Move::from_uci(bad), None, "{}", bad);
+        }
+    }
+
+    #[test]
+    fn fen_rejects_impossible_piece_counts() {
+        // the 218 move position, with all 8 pawns promoted to queens
+        assert!(Board::from_fen("R6R/3Q4/1Q4Q1/4Q3/2Q4Q/Q4Q2/pp1Q4/kBNN1KB1 w - - 0 1").is_ok());
+        // a ninth pawn, and a queen with all 8 pawns still on the board
+        assert_eq!(Board::from_fen("4k3/8/8/8/8/7P/PPPPPPPP/4K3 w - - 0 1"), Err(FenError::InvalidPieceCount));
+        assert_eq!(Board::from_fen("4k3/8/8/8/8/8/PPPPPPPP/3QKQ2 w - - 0 1"), Err(FenError::InvalidPieceCount));
+    }
+
+    #[test]
+    fn fen_enpassant_hashes_like_moves() {
+        // no black pawn can take on e3, so the flag is dropped
+        let b = play(&utils::START_POSITION, "e2e4");
+        let fen_b = Board::from_fen("rnbqkbnr/pppppppp/8/8/4P3/8/PPPP1PPP/RNBQKBNR b KQkq e3 0 1").unwrap();
+        assert_eq!(fen_b.enpassant_files, bitboard::EMPTY_BITRANK);
+        assert_eq!(fen_b.hash, b.hash);
+
+        // the e5 pawn can take on f6, so the flag is kept
+        let b = ["e2e4", "d7d5", "e4e5", "f7f5"].iter().fold(utils::START_POSITION, |b, m| play(&b, m));
+        let fen_b = Board::from_fen("rnbqkbnr/ppp1p1pp/8/3pPp2/8/8/PPPP1PPP/RNBQKBNR w KQkq f6 0 3").unwrap();
+        assert_ne!(fen_b.enpassant_files, bitboard::EMPTY_BITRANK);
+        assert_eq!(fen_b.hash, b.hash);
+    }
+
+    #[test]
+    fn fen_rejects_impossible_enpassant() {
+        // no pawn in front of the target square
+        assert_eq!(Board::from_fen("4k3/8/8/8/4P3/8/8/4K3 w - e6 0 1"), Err(FenError::InvalidEnpassant));
+        // the square the pawn would have come from is occupied
+        assert_eq!(Board::from_fen("4k3/4n3/8/3Pp3/8/8/8/4K3 w - e6 0 1"), Err(FenError::InvalidEnpassant));
+        // wrong rank for the side to move
+        assert_eq!(Board::from_fen("4k3/8/8/3Pp3/8/8/8/4K3 b - e6 0 1"), Err(FenError::InvalidEnpassant));
+    }
+
+    #[test]
+    fn fen_drops_impossible_castling_rights() {
+        // no rook on h1
+        let b = Board::from_fen("4k3/8/8/8/8/8/8/4K3 w K - 0 1").unwrap();
+        assert_eq!(b.castling_rights, Board::from_fen("4k3/8/8/8/8/8/8/4K3 w - - 0 1").unwrap().castling_rights);
+        assert_eq!(b.hash, Board::from_fen("4k3/8/8/8/8/8/8/4K3 w - - 0 1").unwrap().hash);
+        // the black king has moved, and the white rooks are still home
+        let b = Board::from_fen("3k3r/8/8/8/8/8/8/R3K2R w KQk - 0 1").unwrap();
+        assert_eq!(b.to_fen(), "3k3r/8/8/8/8/8/8/R3K2R w KQ - 0 1");
+    }
+
+    #[test]
+    fn fen_rejects_side_not_to_move_in_check() {
+        // white could take the black king
+        assert_eq!(Board::from_fen("4k3/8/8/8/8/8/8/4R1K1 w - - 0 1"), Err(FenError::InvalidPosition));
+        // the side to move being in check is fine
+        assert!(Board::from_fen("4k3/8/8/8/8/8/8/4R1K1 b - - 0 1").is_ok());
+    }
+}
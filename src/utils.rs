@@ -1,8 +1,8 @@
 use crate::bitboard;
-use crate::types;
-use crate::types::{Color, PieceType, Square, CastlingRights, Board, Move, Direction};
+use crate::zobrist;
+use crate::types::{Color, PieceType, Square, CastlingRights, Board, PackedMove, Direction};
 
-pub const START_POSITION: Board = Board {
+const START_POSITION_UNHASHED: Board = Board {
     white_bitboard_pieces: bitboard::WHITE_START,
     black_bitboard_pieces: bitboard::BLACK_START,
     turn: Color::White,
@@ -13,30 +13,65 @@ pub const START_POSITION: Board = Board {
         black_short: true
     },
     enpassant_files: bitboard::EMPTY_BITRANK,
-    all_piece_history: [0u64, 1u64, 2u64, 3u64, 4u64, 5u64, 6u64, 7u64, 8u64, 9u64, 10u64, 11u64, 12u64, 13u64, 14u64, 15u64],
-    all_ptr: 0usize
+    halfmove_clock: 0,
+    fullmove_number: 1,
+    hash: 0u64
+};
+
+pub const START_POSITION: Board = Board {
+    hash: zobrist::hash_board(&START_POSITION_UNHASHED),
+    ..START_POSITION_UNHASHED
 };
 
 // Functions
 
+// returns true if no position before the move from b to next_b can ever occur again
+// this is the case after pawn moves, captures and changes to castling rights
+pub fn is_irreversible(b: &Board, next_b: &Board) -> bool {
+    let num_pieces = (b.white_bitboard_pieces.all | b.black_bitboard_pieces.all).count_ones();
+    let next_num_pieces = (next_b.white_bitboard_pieces.all | next_b.black_bitboard_pieces.all).count_ones();
+    return b.white_bitboard_pieces.pawns != next_b.white_bitboard_pieces.pawns
+        || b.black_bitboard_pieces.pawns != next_b.black_bitboard_pieces.pawns
+        || num_pieces != next_num_pieces
+        || b.castling_rights != next_b.castling_rights;
+}
+
+// returns the type of the piece m captures on b, or PieceType::Null if it isn't a capture
+pub fn get_captured_piece(b: &Board, m: PackedMove) -> PieceType {
+    if !m.is_capture() {
+        return PieceType::Null;
+    }
+    if m.is_enpassant() {
+        return PieceType::Pawn;
+    }
+    return b.get_piece(m.to_square().to_index()).0;
+}
+
+// passes the turn without moving; used for null move pruning and to test whether the side to move is in check
 pub fn apply_null_move(b : &Board) -> Board {
     let mut board = *b;
+    // the pawn that just double pushed can't be captured enpassant anymore after passing
+    board.hash ^= zobrist::enpassant_key(board.enpassant_files);
+    board.enpassant_files = 0;
     board.turn = if board.turn == Color::White {Color::Black} else {Color::White};
+    board.hash ^= zobrist::TURN_KEY;
     return board;
 }
 
-pub fn apply_move(b : &Board, m : Move) -> Board {
+pub fn apply_move(b : &Board, m : PackedMove) -> Board {
     let mut board = *b;
 
     let friendly_pieces = if b.turn == Color::White { &board.white_bitboard_pieces } else { &board.black_bitboard_pieces };
     let enemy_pieces = if b.turn == Color::White { &board.black_bitboard_pieces } else { &board.white_bitboard_pieces };
+    let enemy_color = b.turn.opposite();
 
-    let to_bitboard = m.to_square.to_bitboard();
-    let from_bitboard = m.from_square.to_bitboard();
+    let (from_square, to_square) = (m.from_square(), m.to_square());
+    let to_bitboard = to_square.to_bitboard();
+    let from_bitboard = from_square.to_bitboard();
     let mut mask_from = !from_bitboard;
     let mask_to = !to_bitboard;
 
-    let mut piece_type = PieceType::Null;
+    let piece_type;
     if (friendly_pieces.king & from_bitboard) != 0 {
         piece_type = PieceType::King;
     } else if (friendly_pieces.queens & from_bitboard) != 0 {
@@ -55,33 +90,34 @@ pub fn apply_move(b : &Board, m : Move) -> Board {
         return *b;
     }
 
-    // handle enpassant captures and flag setting
-    if piece_type == PieceType::Pawn {
-        // captures
-        if b.turn == Color::White && ((to_bitboard >> 8*5) & (board.enpassant_files as u64)) != 0 {
-            // if this condition is true, there must have been an enpassant capture on rank 6
-            // we'll clear the pawn by clearing the bit for the captured pawn in mask_from
-            mask_from &= !bitboard::slide1(to_bitboard, Direction::S);
-        }
-        if b.turn == Color::Black && ((to_bitboard >> 8*2) & (board.enpassant_files as u64)) != 0 {
-            // if this condition is true, there must have been an enpassant capture on rank 3
-            // we'll clear the pawn by clearing the bit for the captured pawn in mask_from
-            mask_from &= !bitboard::slide1(to_bitboard, Direction::N);
-        }
+    // the castling rights and enpassant files may change below, so take their old values out of the hash now
+    board.hash ^= zobrist::castling_key(&b.castling_rights) ^ zobrist::enpassant_key(b.enpassant_files);
 
-        // flag setting
-        board.enpassant_files = bitboard::EMPTY_BITRANK; // this gets cleared every move
-        if ((to_bitboard & bitboard::RANK_4) != 0) && ((from_bitboard & bitboard::RANK_2) != 0) {
-            board.enpassant_files = ((from_bitboard >> 8) & 0xFF) as bitboard::Bitrank;
-        }
-        if ((to_bitboard & bitboard::RANK_5) != 0) && ((from_bitboard & bitboard::RANK_7) != 0) {
-            board.enpassant_files = ((from_bitboard >> 8*6) & 0xFF) as bitboard::Bitrank;
+    // the enpassant files are only valid for the move right after a double push
+    board.enpassant_files = bitboard::EMPTY_BITRANK;
+
+    // take the captured piece (if any) out of the hash
+    if m.is_enpassant() {
+        // the captured pawn is behind the square we moved to, so we clear it with the from square
+        let captured_square = to_square.slide(Direction::S.rel(b.turn), 1).unwrap();
+        mask_from &= !captured_square.to_bitboard();
+        board.hash ^= zobrist::piece_key(enemy_color, PieceType::Pawn, captured_square);
+    } else if m.is_capture() {
+        board.hash ^= zobrist::piece_key(enemy_color, get_captured_piece(b, m), to_square);
+    }
+
+    // we only set the enpassant flag when an enemy pawn is next to the pushed pawn, otherwise the capture is impossible
+    // and positions that only differ by an unusable enpassant flag would hash differently
+    if m.is_double_pawn_push() {
+        let adjacent_enemy_pawns = (bitboard::slide1(to_bitboard, Direction::E) | bitboard::slide1(to_bitboard, Direction::W)) & enemy_pieces.pawns;
+        if adjacent_enemy_pawns != 0 {
+            board.enpassant_files = 1u8 << (from_square.to_index() % 8);
         }
     }
 
-    if (enemy_pieces.rooks & to_bitboard) != 0 {
+    if m.is_capture() && (enemy_pieces.rooks & to_bitboard) != 0 {
         // if a rook was captured, we potentially need to clear castling rights
-        match m.to_square {
+        match to_square {
             Square::A1 => board.castling_rights.white_long = false,
             Square::H1 => board.castling_rights.white_short = false,
             Square::A8 => board.castling_rights.black_long = false,
@@ -93,7 +129,7 @@ pub fn apply_move(b : &Board, m : Move) -> Board {
     // clear any lost castling privileges
     if piece_type == PieceType::King {
         // any king move clears castling privileges
-        if (b.turn == Color::White) {
+        if b.turn == Color::White  {
             board.castling_rights.white_long = false;
             board.castling_rights.white_short = false;
         } else {
@@ -102,7 +138,7 @@ pub fn apply_move(b : &Board, m : Move) -> Board {
         }
     } else if piece_type == PieceType::Rook {
         // if a rook moves, it clears castling privileges for its side
-        match m.from_square {
+        match from_square {
             Square::A1 => board.castling_rights.white_long = false,
             Square::H1 => board.castling_rights.white_short = false,
             Square::A8 => board.castling_rights.black_long = false,
@@ -111,38 +147,20 @@ pub fn apply_move(b : &Board, m : Move) -> Board {
         }
     }
 
-    // save off the next board turn here - castling makes pseudomoves which will change the color
-    let next_turn = if b.turn == Color::White {Color::Black} else {Color::White};
-
     // handle castling
-    if piece_type == PieceType::King {
+    if m.is_castle() {
         // everything should automatically be handled elsewhere except moving the rook...
-        // to properly handle that we simply recurse to generate a pseudo-rook-move prior to returning
-        match (m.from_square, m.to_square) {
-            (Square::E1, Square::G1) => {
-                let rook_move = Move {from_square: Square::H1, to_square: Square::F1, promote_type: PieceType::Null};
-                board = apply_move(&board, rook_move);
-                board.all_ptr = (board.all_ptr-1) % 16;
-            }
-            (Square::E1, Square::C1) => {
-                let rook_move = Move {from_square: Square::A1, to_square: Square::D1, promote_type: PieceType::Null};
-                board = apply_move(&board, rook_move);
-                board.all_ptr = (board.all_ptr-1) % 16;
-            }
-            (Square::E8, Square::G8) => {
-                let rook_move = Move {from_square: Square::H8, to_square: Square::F8, promote_type: PieceType::Null};
-                board = apply_move(&board, rook_move);
-                board.all_ptr = (board.all_ptr-1) % 16;
-            }
-            (Square::E8, Square::C8) => {
-                let rook_move = Move {from_square: Square::A8, to_square: Square::D8, promote_type: PieceType::Null};
-                board = apply_move(&board, rook_move);
-                board.all_ptr = (board.all_ptr-1) % 16;
-            }
-            _ => { }
-        }
+        let (rook_from, rook_to) = if m.flags() == PackedMove::KING_CASTLE { (Square::H1, Square::F1) } else { (Square::A1, Square::D1) };
+        let (rook_from, rook_to) = (rook_from.rel(b.turn), rook_to.rel(b.turn));
+        let rooks = if b.turn == Color::White { &mut board.white_bitboard_pieces.rooks } else { &mut board.black_bitboard_pieces.rooks };
+        *rooks = (*rooks & !rook_from.to_bitboard()) | rook_to.to_bitboard();
+        board.hash ^= zobrist::piece_key(b.turn, PieceType::Rook, rook_from) ^ zobrist::piece_key(b.turn, PieceType::Rook, rook_to);
     }
 
+    // move the piece in the hash, accounting for promotions
+    let placed_type = if m.is_promotion() { m.promote_type() } else { piece_type };
+    board.hash ^= zobrist::piece_key(b.turn, piece_type, from_square) ^ zobrist::piece_key(b.turn, placed_type, to_square);
+
     // clear all the from squares and the to squares on our bitboard
     board.white_bitboard_pieces.king &= mask_from & mask_to;
     board.white_bitboard_pieces.queens &= mask_from & mask_to;
@@ -158,44 +176,15 @@ pub fn apply_move(b : &Board, m : Move) -> Board {
     board.black_bitboard_pieces.pawns &= mask_from & mask_to;
 
     // next, let's set the bitboard for where it moved to
-    if b.turn == Color::White {
-        match piece_type {
-            PieceType::King => board.white_bitboard_pieces.king |= to_bitboard,
-            PieceType::Queen => board.white_bitboard_pieces.queens |= to_bitboard,
-            PieceType::Rook => board.white_bitboard_pieces.rooks |= to_bitboard,
-            PieceType::Bishop => board.white_bitboard_pieces.bishops |= to_bitboard,
-            PieceType::Knight => board.white_bitboard_pieces.knights |= to_bitboard,
-            PieceType::Pawn => {
-                match m.promote_type {
-                    // handle promotions
-                    PieceType::Queen => board.white_bitboard_pieces.queens |= to_bitboard,
-                    PieceType::Rook => board.white_bitboard_pieces.rooks |= to_bitboard,
-                    PieceType::Bishop => board.white_bitboard_pieces.bishops |= to_bitboard,
-                    PieceType::Knight => board.white_bitboard_pieces.knights |= to_bitboard,
-                    _ => board.white_bitboard_pieces.pawns |= to_bitboard
-                }
-            },
-            _ => { }
-        }
-    } else {
-        match piece_type {
-            PieceType::King => board.black_bitboard_pieces.king |= to_bitboard,
-            PieceType::Queen => board.black_bitboard_pieces.queens |= to_bitboard,
-            PieceType::Rook => board.black_bitboard_pieces.rooks |= to_bitboard,
-            PieceType::Bishop => board.black_bitboard_pieces.bishops |= to_bitboard,
-            PieceType::Knight => board.black_bitboard_pieces.knights |= to_bitboard,
-            PieceType::Pawn => {
-                match m.promote_type {
-                    // handle promotions
-                    PieceType::Queen => board.black_bitboard_pieces.queens |= to_bitboard,
-                    PieceType::Rook => board.black_bitboard_pieces.rooks |= to_bitboard,
-                    PieceType::Bishop => board.black_bitboard_pieces.bishops |= to_bitboard,
-                    PieceType::Knight => board.black_bitboard_pieces.knights |= to_bitboard,
-                    _ => board.black_bitboard_pieces.pawns |= to_bitboard
-                }
-            },
-            _ => { }
-        }
+    let pieces = if b.turn == Color::White { &mut board.white_bitboard_pieces } else { &mut board.black_bitboard_pieces };
+    match placed_type {
+        PieceType::King => pieces.king |= to_bitboard,
+        PieceType::Queen => pieces.queens |= to_bitboard,
+        PieceType::Rook => pieces.rooks |= to_bitboard,
+        PieceType::Bishop => pieces.bishops |= to_bitboard,
+        PieceType::Knight => pieces.knights |= to_bitboard,
+        PieceType::Pawn => pieces.pawns |= to_bitboard,
+        _ => { }
     }
     
     // set the "all" bitboards
@@ -211,14 +200,21 @@ pub fn apply_move(b : &Board, m : Move) -> Board {
         | board.black_bitboard_pieces.bishops
         | board.black_bitboard_pieces.knights
         | board.black_bitboard_pieces.pawns;
-    
-    // set the "all piece history"
-    let all_pieces = board.white_bitboard_pieces.all | board.black_bitboard_pieces.all;
-    board.all_piece_history[board.all_ptr] = all_pieces;
-    board.all_ptr = (board.all_ptr+1) % 16;
+
+    // update the move counters
+    board.halfmove_clock = if piece_type == PieceType::Pawn || m.is_capture() { 0 } else { b.halfmove_clock + 1 };
+    if b.turn == Color::Black {
+        board.fullmove_number = b.fullmove_number + 1;
+    }
 
     // update the board's color
-    board.turn = next_turn;
+    board.turn = if b.turn == Color::White {Color::Black} else {Color::White};
+    board.hash ^= zobrist::TURN_KEY;
+
+    // put the new castling rights and enpassant files into the hash
+    board.hash ^= zobrist::castling_key(&board.castling_rights) ^ zobrist::enpassant_key(board.enpassant_files);
+
+    debug_assert_eq!(board.hash, zobrist::hash_board(&board), "incremental hash mismatch after {:?}", m);
 
     // and, we're done!
     return board;
@@ -0,0 +1,105 @@
+use crate::types::{Color, PieceType, Square, CastlingRights, Board};
+use crate::bitboard;
+
+// key layout: 2 colors * 6 piece types * 64 squares, then side to move, then castling rights, then enpassant files
+const PIECE_KEYS_OFFSET: usize = 0;
+const TURN_KEY_OFFSET: usize = 2*6*64;
+const CASTLING_KEYS_OFFSET: usize = TURN_KEY_OFFSET + 1;
+const ENPASSANT_KEYS_OFFSET: usize = CASTLING_KEYS_OFFSET + 4;
+const NUM_KEYS: usize = ENPASSANT_KEYS_OFFSET + 8;
+
+// the keys are generated at compile time from a fixed seed so hashes are stable between runs
+const KEYS: [u64; NUM_KEYS] = gen_keys(0x676F_6C64_7963_6865u64);
+
+pub const TURN_KEY: u64 = KEYS[TURN_KEY_OFFSET];
+
+const fn splitmix64(state: u64) -> (u64, u64) {
+    let next_state = state.wrapping_add(0x9E37_79B9_7F4A_7C15u64);
+    let mut z = next_state;
+    z = (z ^ (z >> 30)).wrapping_mul(0xBF58_476D_1CE4_E5B9u64);
+    z = (z ^ (z >> 27)).wrapping_mul(0x94D0_49BB_1331_11EBu64);
+    return (next_state, z ^ (z >> 31));
+}
+
+const fn gen_keys(seed: u64) -> [u64; NUM_KEYS] {
+    let mut keys = [0u64; NUM_KEYS];
+    let mut state = seed;
+    let mut i = 0;
+    while i < NUM_KEYS {
+        let (next_state, key) = splitmix64(state);
+        keys[i] = key;
+        state = next_state;
+        i += 1;
+    }
+    return keys;
+}
+
+const fn piece_key_idx(color_idx: usize, piece_idx: usize, square_idx: usize) -> usize {
+    return PIECE_KEYS_OFFSET + (color_idx*6 + piece_idx)*64 + square_idx;
+}
+
+// p must not be PieceType::Null
+pub fn piece_key(c: Color, p: PieceType, sq: Square) -> u64 {
+    return KEYS[piece_key_idx(c as usize, p as usize, sq.to_index())];
+}
+
+pub const fn castling_key(cr: &CastlingRights) -> u64 {
+    let mut key = 0u64;
+    if cr.white_long {
+        key ^= KEYS[CASTLING_KEYS_OFFSET];
+    }
+    if cr.white_short {
+        key ^= KEYS[CASTLING_KEYS_OFFSET + 1];
+    }
+    if cr.black_long {
+        key ^= KEYS[CASTLING_KEYS_OFFSET + 2];
+    }
+    if cr.black_short {
+        key ^= KEYS[CASTLING_KEYS_OFFSET + 3];
+    }
+    return key;
+}
+
+pub const fn enpassant_key(enpassant_files: bitboard::Bitrank) -> u64 {
+    let mut key = 0u64;
+    let mut files = enpassant_files;
+    while files != 0 {
+        let idx = files.trailing_zeros() as usize;
+        key ^= KEYS[ENPASSANT_KEYS_OFFSET + idx];
+        files &= files - 1;
+    }
+    return key;
+}
+
+const fn hash_bitboard(bb: bitboard::Bitboard, color_idx: usize, piece_idx: usize) -> u64 {
+    let mut key = 0u64;
+    let mut bb = bb;
+    while bb != 0 {
+        let idx = bb.trailing_zeros() as usize;
+        key ^= KEYS[piece_key_idx(color_idx, piece_idx, idx)];
+        bb &= bb - 1;
+    }
+    return key;
+}
+
+const fn hash_pieces(p: &bitboard::Pieces, color_idx: usize) -> u64 {
+    return hash_bitboard(p.king, color_idx, PieceType::King as usize)
+        ^ hash_bitboard(p.queens, color_idx, PieceType::Queen as usize)
+        ^ hash_bitboard(p.rooks, color_idx, PieceType::Rook as usize)
+        ^ hash_bitboard(p.bishops, color_idx, PieceType::Bishop as usize)
+        ^ hash_bitboard(p.knights, color_idx, PieceType::Knight as usize)
+        ^ hash_bitboard(p.pawns, color_idx, PieceType::Pawn as usize);
+}
+
+// computes the full hash of a board from scratch
+// apply_move keeps board.hash up to date incrementally, so this should only be needed when creating boards
+pub const fn hash_board(b: &Board) -> u64 {
+    let mut key = hash_pieces(&b.white_bitboard_pieces, Color::White as usize)
+        ^ hash_pieces(&b.black_bitboard_pieces, Color::Black as usize)
+        ^ castling_key(&b.castling_rights)
+        ^ enpassant_key(b.enpassant_files);
+    if matches!(b.turn, Color::Black) {
+        key ^= TURN_KEY;
+    }
+    return key;
+}